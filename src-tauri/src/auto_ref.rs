@@ -0,0 +1,166 @@
+use crate::banchobot_parser::LobbyEvent;
+use crate::types::LobbyState;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoRefConfig {
+    /// Countdown passed to `!mp start` once everyone is ready.
+    pub start_delay: u32,
+    /// When set, a `!mp timer` of this many seconds runs after every `!mp map` and
+    /// the map starts when it runs out, ready or not.
+    pub ready_timer: Option<u32>,
+    /// A player leaving within this many seconds of the start aborts the map so it
+    /// can be replayed.
+    pub abort_window: u32,
+    /// Sent to the lobby after such an abort.
+    pub abort_message: String,
+}
+
+/// The messages auto-ref sends in reply to an event, given the lobby state after the
+/// event was applied.
+pub fn react(
+    config: &AutoRefConfig,
+    lobby: &LobbyState,
+    event: &LobbyEvent,
+    timestamp: u64,
+) -> Vec<String> {
+    let in_progress = lobby.match_status == "active";
+
+    match event {
+        LobbyEvent::AllPlayersReady if !in_progress && lobby.current_map.is_some() => {
+            vec![start_command(config)]
+        }
+        LobbyEvent::CountdownEnded { aborted: false }
+            if !in_progress && config.ready_timer.is_some() && lobby.current_map.is_some() =>
+        {
+            vec![start_command(config)]
+        }
+        // Only BanchoBot's reply to `!mp map`, a `!mp settings` reply repeats the
+        // current map and must not restart the timer
+        LobbyEvent::MapChanged {
+            by_referee: true, ..
+        } if !in_progress => match config.ready_timer {
+            Some(seconds) => vec![format!("!mp timer {}", seconds)],
+            None => Vec::new(),
+        },
+        LobbyEvent::PlayerLeft { .. } if in_progress => match lobby.match_start_time {
+            Some(started) if timestamp.saturating_sub(started) <= config.abort_window as u64 => {
+                let mut messages = vec!["!mp abort".to_string()];
+                if !config.abort_message.is_empty() {
+                    messages.push(config.abort_message.clone());
+                }
+                messages
+            }
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+fn start_command(config: &AutoRefConfig) -> String {
+    if config.start_delay == 0 {
+        "!mp start".to_string()
+    } else {
+        format!("!mp start {}", config.start_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::banchobot_parser::BanchoBotParser;
+    use crate::lobby_reducer::apply_event;
+    use crate::types::CurrentMap;
+
+    fn config() -> AutoRefConfig {
+        AutoRefConfig {
+            start_delay: 10,
+            ready_timer: Some(120),
+            abort_window: 30,
+            abort_message: "Replaying the map".to_string(),
+        }
+    }
+
+    fn lobby_with_map() -> LobbyState {
+        let mut lobby = LobbyState::new();
+        apply_event(
+            &mut lobby,
+            &LobbyEvent::MapChanged {
+                map: CurrentMap {
+                    beatmap_id: 75,
+                    title: "DISCO PRINCE".to_string(),
+                    difficulty: "Normal".to_string(),
+                    artist: "Kenji Ninuma".to_string(),
+                },
+                by_referee: true,
+            },
+            0,
+        );
+        lobby
+    }
+
+    #[test]
+    fn starts_when_ready_or_timer_runs_out() {
+        let lobby = lobby_with_map();
+        let finished = LobbyEvent::CountdownEnded { aborted: false };
+
+        assert_eq!(
+            react(&config(), &lobby, &LobbyEvent::AllPlayersReady, 0),
+            vec!["!mp start 10"]
+        );
+        assert_eq!(react(&config(), &lobby, &finished, 0), vec!["!mp start 10"]);
+        assert!(react(
+            &config(),
+            &lobby,
+            &LobbyEvent::CountdownEnded { aborted: true },
+            0
+        )
+        .is_empty());
+
+        let manual_timers = AutoRefConfig {
+            ready_timer: None,
+            ..config()
+        };
+        assert!(react(&manual_timers, &lobby, &finished, 0).is_empty());
+    }
+
+    #[test]
+    fn aborts_on_early_disconnect_only() {
+        let mut lobby = lobby_with_map();
+        apply_event(&mut lobby, &LobbyEvent::MatchStarted, 100);
+        let left = LobbyEvent::PlayerLeft {
+            username: "Cookiezi".to_string(),
+        };
+
+        assert_eq!(
+            react(&config(), &lobby, &left, 120),
+            vec!["!mp abort", "Replaying the map"]
+        );
+        assert!(react(&config(), &lobby, &left, 131).is_empty());
+    }
+
+    #[test]
+    fn restarts_the_timer_on_map_changes_only() {
+        let mut lobby = lobby_with_map();
+        let mut feed = |text: &str| {
+            let event = BanchoBotParser::parse_line(text).unwrap();
+            apply_event(&mut lobby, &event, 0);
+            react(&config(), &lobby, &event, 0)
+        };
+
+        for line in [
+            "Room name: OWC: (Team A) vs (Team B), History: https://osu.ppy.sh/mp/1",
+            "Beatmap: https://osu.ppy.sh/b/75 Kenji Ninuma - DISCO PRINCE [Normal]",
+            "Team mode: TeamVs, Win condition: ScoreV2",
+            "Active mods: Freemod",
+            "Players: 2",
+        ] {
+            assert!(feed(line).is_empty(), "{} restarted the timer", line);
+        }
+        assert_eq!(
+            feed("Changed beatmap to https://osu.ppy.sh/b/129891 xi - FREEDOM DiVE"),
+            vec!["!mp timer 120"]
+        );
+    }
+}
@@ -1,128 +1,221 @@
 use crate::types::*;
-use regex::Regex;
-use std::sync::OnceLock;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tauri::Emitter;
-
-/// Compiles a regex pattern once and reuses it for all subsequent calls.
-macro_rules! static_regex {
-    ($pattern:expr) => {{
-        static RE: OnceLock<Regex> = OnceLock::new();
-        RE.get_or_init(|| Regex::new($pattern).expect("Invalid regex pattern"))
-    }};
+use serde::{Deserialize, Serialize};
+
+/// A change in a multiplayer lobby described by a single chat line.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum LobbyEvent {
+    /// `Room name: ...` from `!mp settings` or `Room name updated to "..."`.
+    RoomNameChanged {
+        name: String,
+    },
+    TeamModeChanged {
+        team_mode: String,
+        win_condition: String,
+    },
+    /// Reply to `!mp set`, the size is left out when it wasn't given.
+    MatchSettingsChanged {
+        size: Option<u8>,
+        team_mode: String,
+        win_condition: String,
+    },
+    SizeChanged {
+        size: u8,
+    },
+    /// `Players: N` from `!mp settings`, the number of occupied slots.
+    PlayerCountReported {
+        count: u8,
+    },
+    PasswordChanged {
+        has_password: bool,
+    },
+    LockChanged {
+        locked: bool,
+    },
+    /// A `Slot N ...` line from `!mp settings`.
+    SlotUpdated {
+        slot: u8,
+        player: Player,
+    },
+    /// `Changed beatmap to ...`, the reply to our own `!mp map`, or the host's
+    /// `Beatmap changed to: ...`.
+    MapChanged {
+        map: CurrentMap,
+        by_referee: bool,
+    },
+    /// `Beatmap: ...` from `!mp settings`, the map the lobby is already on.
+    MapReported {
+        map: CurrentMap,
+    },
+    ModsChanged {
+        mods: Vec<String>,
+        freemod: bool,
+    },
+    PlayerJoined {
+        username: String,
+        slot: u8,
+        team: Option<String>,
+    },
+    PlayerLeft {
+        username: String,
+    },
+    PlayerKicked {
+        username: String,
+    },
+    PlayerMoved {
+        username: String,
+        slot: u8,
+    },
+    PlayerChangedTeam {
+        username: String,
+        team: String,
+    },
+    HostChanged {
+        username: String,
+    },
+    HostCleared,
+    AllPlayersReady,
+    MatchStarted,
+    MatchAborted,
+    PlayerFinished {
+        username: String,
+        score: u64,
+        passed: bool,
+    },
+    MatchFinished,
+    PlayerRolled {
+        username: String,
+        value: u64,
+    },
+    CountdownStarted {
+        seconds: u32,
+    },
+    CountdownEnded {
+        aborted: bool,
+    },
+    MatchClosed,
 }
 
-pub struct BanchoBotParser;
-
-impl BanchoBotParser {
-    fn emit_lobby_update(
-        channel: &str,
-        lobby: &LobbyState,
-        active_room_id: Option<&str>,
-        app_handle: &tauri::AppHandle,
-    ) {
-        let is_active = active_room_id == Some(channel);
-
-        if is_active {
-            let _ = app_handle.emit(
-                "active-room-lobby-state-updated",
-                serde_json::json!({ "lobbyState": lobby }),
-            );
+impl LobbyEvent {
+    /// Short name used to label the event in match logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LobbyEvent::RoomNameChanged { .. }
+            | LobbyEvent::TeamModeChanged { .. }
+            | LobbyEvent::MatchSettingsChanged { .. }
+            | LobbyEvent::SizeChanged { .. }
+            | LobbyEvent::PlayerCountReported { .. }
+            | LobbyEvent::PasswordChanged { .. }
+            | LobbyEvent::LockChanged { .. }
+            | LobbyEvent::MapReported { .. } => "settings",
+            LobbyEvent::SlotUpdated { .. } => "slot",
+            LobbyEvent::MapChanged { .. } => "map",
+            LobbyEvent::ModsChanged { .. } => "mods",
+            LobbyEvent::PlayerJoined { .. } => "join",
+            LobbyEvent::PlayerLeft { .. } => "leave",
+            LobbyEvent::PlayerKicked { .. } => "kick",
+            LobbyEvent::PlayerMoved { .. } => "move",
+            LobbyEvent::PlayerChangedTeam { .. } => "team",
+            LobbyEvent::HostChanged { .. } | LobbyEvent::HostCleared => "host",
+            LobbyEvent::AllPlayersReady => "ready",
+            LobbyEvent::MatchStarted => "start",
+            LobbyEvent::MatchAborted => "abort",
+            LobbyEvent::PlayerFinished { .. } => "result",
+            LobbyEvent::MatchFinished => "finish",
+            LobbyEvent::PlayerRolled { .. } => "roll",
+            LobbyEvent::CountdownStarted { .. } | LobbyEvent::CountdownEnded { .. } => "countdown",
+            LobbyEvent::MatchClosed => "close",
         }
     }
+}
+
+/// Turns chat lines in `#mp_` channels into `LobbyEvent`s. It has no state and
+/// never touches the app, see `lobby_sync` for where events get applied.
+pub struct BanchoBotParser;
 
-    pub fn parse_irc_message(
-        message: &IrcMessage,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) -> bool {
+impl BanchoBotParser {
+    /// Parses a chat message and returns the lobby it affects along with the event.
+    pub fn parse(message: &IrcMessage) -> Option<(String, LobbyEvent)> {
         if message.username != "BanchoBot" {
             // Handle user leaving lobby
-            if let Some(captures) =
-                static_regex!(r"^(.+) left (#mp_\d+)$").captures(&message.message)
-            {
-                let username = captures.get(1).unwrap().as_str();
-                let channel = captures.get(2).unwrap().as_str();
-                Self::remove_player_by_username(username, channel, state, app_handle);
-                return true;
-            }
-            return false;
+            let captures = static_regex!(r"^(.+) left (#mp_\d+)$").captures(&message.message)?;
+            return Some((
+                captures.get(2).unwrap().as_str().to_string(),
+                LobbyEvent::PlayerLeft {
+                    username: captures.get(1).unwrap().as_str().to_string(),
+                },
+            ));
         }
 
-        Self::parse_banchobot_message(message, state, app_handle)
-    }
-
-    fn parse_banchobot_message(
-        message: &IrcMessage,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) -> bool {
-        if message.username != "BanchoBot" {
-            return false;
+        if !message.room_id.starts_with("#mp_") {
+            return None;
         }
 
-        let text = &message.message;
-        let channel = &message.room_id;
+        Self::parse_line(&message.message).map(|event| (message.room_id.clone(), event))
+    }
 
-        if !channel.starts_with("#mp_") {
-            return false;
-        }
+    /// Parses BanchoBot's reply to `!mp make`, returning the match id and name.
+    pub fn parse_match_created(text: &str) -> Option<(u64, String)> {
+        let captures =
+            static_regex!(r"^Created the tournament match https://osu\.ppy\.sh/mp/(\d+) (.+)$")
+                .captures(text)?;
+        let match_id = captures.get(1).unwrap().as_str().parse::<u64>().ok()?;
+        Some((match_id, captures.get(2).unwrap().as_str().to_string()))
+    }
 
+    /// Parses a single line BanchoBot sent to a multiplayer lobby.
+    pub fn parse_line(text: &str) -> Option<LobbyEvent> {
         // Room name pattern
         if let Some(captures) =
             static_regex!(r"^Room name: (.+), History: https://osu\.ppy\.sh/mp/(\d+)$")
                 .captures(text)
         {
-            let room_name = captures.get(1).unwrap().as_str();
-            Self::update_lobby_settings(
-                channel,
-                |settings| {
-                    settings.room_name = room_name.to_string();
-                },
-                state,
-                app_handle,
-            );
-            return true;
+            return Some(LobbyEvent::RoomNameChanged {
+                name: captures.get(1).unwrap().as_str().to_string(),
+            });
         }
 
         // Team mode and win condition
         if let Some(captures) =
             static_regex!(r"^Team mode: (.+), Win condition: (.+)$").captures(text)
         {
-            let team_mode = match captures.get(1).unwrap().as_str() {
-                "Head To Head" => "HeadToHead",
-                "Tag Coop" => "TagCoop",
-                "Team Vs" => "TeamVs",
-                "Tag Team Vs" => "TagTeamVs",
-                _ => "HeadToHead",
-            };
-
-            let win_condition = match captures.get(2).unwrap().as_str() {
-                "Score" => "Score",
-                "Accuracy" => "Accuracy",
-                "Combo" => "Combo",
-                "Score V2" => "ScoreV2",
-                _ => "Score",
-            };
-
-            Self::update_lobby_settings(
-                channel,
-                |settings| {
-                    settings.team_mode = team_mode.to_string();
-                    settings.win_condition = win_condition.to_string();
-                },
-                state,
-                app_handle,
-            );
-            return true;
+            return Some(LobbyEvent::TeamModeChanged {
+                team_mode: Self::normalize_team_mode(captures.get(1).unwrap().as_str()),
+                win_condition: Self::normalize_win_condition(captures.get(2).unwrap().as_str()),
+            });
+        }
+
+        // Reply to !mp set
+        if let Some(captures) =
+            static_regex!(r"^Changed match settings to (?:(\d+) slots, )?([^,]+), ([^,]+)$")
+                .captures(text)
+        {
+            return Some(LobbyEvent::MatchSettingsChanged {
+                size: captures.get(1).and_then(|m| m.as_str().parse().ok()),
+                team_mode: Self::normalize_team_mode(captures.get(2).unwrap().as_str()),
+                win_condition: Self::normalize_win_condition(captures.get(3).unwrap().as_str()),
+            });
+        }
+
+        // Reply to !mp size
+        if let Some(captures) = static_regex!(r"^Changed match to size (\d+)$").captures(text) {
+            if let Ok(size) = captures.get(1).unwrap().as_str().parse::<u8>() {
+                return Some(LobbyEvent::SizeChanged { size });
+            }
+        }
+
+        // Player count (from !mp settings)
+        if let Some(captures) = static_regex!(r"^Players: (\d+)$").captures(text) {
+            if let Ok(count) = captures.get(1).unwrap().as_str().parse::<u8>() {
+                return Some(LobbyEvent::PlayerCountReported { count });
+            }
         }
 
         // Slot info
         if let Some(captures) = static_regex!(r"^Slot (\d+)\s+(.+)$").captures(text) {
-            if let Ok(slot_id) = captures.get(1).unwrap().as_str().parse::<u8>() {
-                let slot_info = captures.get(2).unwrap().as_str();
-                Self::parse_slot_info(slot_info, slot_id, channel, state, app_handle);
-                return true;
+            if let Ok(slot) = captures.get(1).unwrap().as_str().parse::<u8>() {
+                return Self::parse_slot_info(captures.get(2).unwrap().as_str())
+                    .map(|player| LobbyEvent::SlotUpdated { slot, player });
             }
         }
 
@@ -134,73 +227,50 @@ impl BanchoBotParser {
                 let full_title = captures.get(2).unwrap().as_str();
                 let difficulty = captures.get(3).unwrap().as_str();
 
-                if let Some(title_captures) = static_regex!(r"^(.+) - (.+)$").captures(full_title) {
-                    let artist = title_captures.get(1).unwrap().as_str();
-                    let title = title_captures.get(2).unwrap().as_str();
-
-                    Self::update_current_map(
-                        channel,
-                        CurrentMap {
-                            beatmap_id,
-                            artist: artist.to_string(),
-                            title: title.to_string(),
-                            difficulty: difficulty.to_string(),
-                        },
-                        state,
-                        app_handle,
-                    );
-                }
-                return true;
-            }
-        }
-
-        // Changed beatmap to (from !mp map {map_id})
-        if let Some(captures) =
-            static_regex!(r"^Changed beatmap to https://osu\.ppy\.sh/b/(\d+) (.+) - (.+)$")
-                .captures(text)
-        {
-            if let Ok(beatmap_id) = captures.get(1).unwrap().as_str().parse::<u64>() {
-                Self::update_current_map(
-                    channel,
-                    CurrentMap {
+                let title_captures = static_regex!(r"^(.+) - (.+)$").captures(full_title)?;
+                return Some(LobbyEvent::MapReported {
+                    map: CurrentMap {
                         beatmap_id,
-                        artist: captures.get(2).unwrap().as_str().to_string(),
-                        title: captures.get(3).unwrap().as_str().to_string(),
-                        difficulty: String::new(),
+                        artist: title_captures.get(1).unwrap().as_str().to_string(),
+                        title: title_captures.get(2).unwrap().as_str().to_string(),
+                        difficulty: difficulty.to_string(),
                     },
-                    state,
-                    app_handle,
-                );
-                return true;
+                });
             }
         }
 
-        // Current beatmap without difficulty bracket (from !mp map {map_id})
-        if let Some(captures) =
-            static_regex!(r"^Beatmap: https://osu\.ppy\.sh/b/(\d+) (.+) - (.+)$").captures(text)
+        // Changed beatmap to (from !mp map {map_id}), or a settings line without a difficulty
+        if let Some(captures) = static_regex!(
+            r"^(Changed beatmap to|Beatmap:) https://osu\.ppy\.sh/b/(\d+) (.+) - (.+)$"
+        )
+        .captures(text)
         {
-            if let Ok(beatmap_id) = captures.get(1).unwrap().as_str().parse::<u64>() {
-                Self::update_current_map(
-                    channel,
-                    CurrentMap {
-                        beatmap_id,
-                        artist: captures.get(2).unwrap().as_str().to_string(),
-                        title: captures.get(3).unwrap().as_str().to_string(),
-                        difficulty: String::new(),
-                    },
-                    state,
-                    app_handle,
-                );
-                return true;
+            if let Ok(beatmap_id) = captures.get(2).unwrap().as_str().parse::<u64>() {
+                let map = CurrentMap {
+                    beatmap_id,
+                    artist: captures.get(3).unwrap().as_str().to_string(),
+                    title: captures.get(4).unwrap().as_str().to_string(),
+                    difficulty: String::new(),
+                };
+                return Some(if captures.get(1).unwrap().as_str() == "Beatmap:" {
+                    LobbyEvent::MapReported { map }
+                } else {
+                    LobbyEvent::MapChanged {
+                        map,
+                        by_referee: true,
+                    }
+                });
             }
         }
 
         // Active mods
         if let Some(captures) = static_regex!(r"^Active mods: (.+)$").captures(text) {
-            let mods_str = captures.get(1).unwrap().as_str();
-            let mut mods: Vec<String> = mods_str
+            let mut mods: Vec<String> = captures
+                .get(1)
+                .unwrap()
+                .as_str()
                 .split(", ")
-                .map(|m| Self::normalize_mod_name(m))
+                .map(Self::normalize_mod_name)
                 .collect();
 
             let mut freemod = false;
@@ -213,8 +283,7 @@ impl BanchoBotParser {
                 }
             });
 
-            Self::update_mods(channel, mods, freemod, state, app_handle);
-            return true;
+            return Some(LobbyEvent::ModsChanged { mods, freemod });
         }
 
         // Beatmap changed (manually by user in lobby)
@@ -224,18 +293,15 @@ impl BanchoBotParser {
         .captures(text)
         {
             if let Ok(beatmap_id) = captures.get(4).unwrap().as_str().parse::<u64>() {
-                Self::update_current_map(
-                    channel,
-                    CurrentMap {
+                return Some(LobbyEvent::MapChanged {
+                    map: CurrentMap {
                         beatmap_id,
                         artist: captures.get(1).unwrap().as_str().to_string(),
                         title: captures.get(2).unwrap().as_str().to_string(),
                         difficulty: captures.get(3).unwrap().as_str().to_string(),
                     },
-                    state,
-                    app_handle,
-                );
-                return true;
+                    by_referee: false,
+                });
             }
         }
 
@@ -243,153 +309,156 @@ impl BanchoBotParser {
         if let Some(captures) =
             static_regex!(r"^(.+) joined in slot (\d+)( for team (red|blue))?\.?$").captures(text)
         {
-            if let Ok(slot_id) = captures.get(2).unwrap().as_str().parse::<u8>() {
-                let team = captures.get(4).map(|m| m.as_str().to_string());
-
-                Self::add_player(
-                    channel,
-                    slot_id,
-                    Player {
-                        username: captures.get(1).unwrap().as_str().to_string(),
-                        team,
-                        is_ready: false,
-                        is_playing: false,
-                        is_host: false,
-                    },
-                    state,
-                    app_handle,
-                );
-                return true;
+            if let Ok(slot) = captures.get(2).unwrap().as_str().parse::<u8>() {
+                return Some(LobbyEvent::PlayerJoined {
+                    username: captures.get(1).unwrap().as_str().to_string(),
+                    slot,
+                    team: captures.get(4).map(|m| m.as_str().to_string()),
+                });
             }
         }
 
         // Player left
         if let Some(captures) = static_regex!(r"^(.+) left the game\.?$").captures(text) {
-            Self::remove_player_by_username(
-                captures.get(1).unwrap().as_str(),
-                channel,
-                state,
-                app_handle,
-            );
-            return true;
-        }
-
-        // Match status changes
-        if text == "All players are ready" {
-            Self::update_match_status(channel, "ready", state, app_handle);
-            return true;
+            return Some(LobbyEvent::PlayerLeft {
+                username: captures.get(1).unwrap().as_str().to_string(),
+            });
         }
 
-        if text == "The match has started!" {
-            Self::update_match_status(channel, "active", state, app_handle);
-            return true;
+        // Fixed status lines
+        match text {
+            "All players are ready" => return Some(LobbyEvent::AllPlayersReady),
+            "The match has started!" => return Some(LobbyEvent::MatchStarted),
+            "The match has finished!" => return Some(LobbyEvent::MatchFinished),
+            "Closed the match" | "Stopped the match" => return Some(LobbyEvent::MatchClosed),
+            "The match was aborted" => return Some(LobbyEvent::MatchAborted),
+            "Cleared match host" => return Some(LobbyEvent::HostCleared),
+            "Disabled all mods, enabled FreeMod" => {
+                return Some(LobbyEvent::ModsChanged {
+                    mods: Vec::new(),
+                    freemod: true,
+                })
+            }
+            "Disabled all mods, disabled FreeMod" => {
+                return Some(LobbyEvent::ModsChanged {
+                    mods: Vec::new(),
+                    freemod: false,
+                })
+            }
+            "Countdown finished" => return Some(LobbyEvent::CountdownEnded { aborted: false }),
+            "Countdown aborted" => return Some(LobbyEvent::CountdownEnded { aborted: true }),
+            "Changed the match password" => {
+                return Some(LobbyEvent::PasswordChanged { has_password: true })
+            }
+            "Removed the match password" => {
+                return Some(LobbyEvent::PasswordChanged {
+                    has_password: false,
+                })
+            }
+            "Locked the match" => return Some(LobbyEvent::LockChanged { locked: true }),
+            "Unlocked the match" => return Some(LobbyEvent::LockChanged { locked: false }),
+            _ => {}
         }
 
-        if text == "Cleared match host" {
-            Self::clear_host(channel, state, app_handle);
-            return true;
+        if text.contains("Aborted the match") {
+            return Some(LobbyEvent::MatchAborted);
         }
 
         // Host changed
         if let Some(captures) = static_regex!(r"^Changed match host to (.+)$").captures(text) {
-            Self::update_host(
-                channel,
-                captures.get(1).unwrap().as_str(),
-                state,
-                app_handle,
-            );
-            return true;
+            return Some(LobbyEvent::HostChanged {
+                username: captures.get(1).unwrap().as_str().to_string(),
+            });
         }
 
         // Player moved to different slot
         if let Some(captures) = static_regex!(r"^(.+) moved to slot (\d+)$").captures(text) {
-            if let Ok(new_slot_id) = captures.get(2).unwrap().as_str().parse::<u8>() {
-                Self::move_player_to_slot(
-                    channel,
-                    captures.get(1).unwrap().as_str(),
-                    new_slot_id,
-                    None,
-                    state,
-                    app_handle,
-                );
-                return true;
+            if let Ok(slot) = captures.get(2).unwrap().as_str().parse::<u8>() {
+                return Some(LobbyEvent::PlayerMoved {
+                    username: captures.get(1).unwrap().as_str().to_string(),
+                    slot,
+                });
             }
         }
 
-        // Match aborted or finished
-        if text == "The match was aborted" || text.contains("Aborted the match") {
-            Self::update_match_status(channel, "idle", state, app_handle);
-            return true;
+        // Player moved by the referee (!mp move)
+        if let Some(captures) = static_regex!(r"^Moved (.+) into slot (\d+)$").captures(text) {
+            if let Ok(slot) = captures.get(2).unwrap().as_str().parse::<u8>() {
+                return Some(LobbyEvent::PlayerMoved {
+                    username: captures.get(1).unwrap().as_str().to_string(),
+                    slot,
+                });
+            }
         }
 
-        if text.contains("finished playing") || text.contains("The match has finished!") {
-            Self::update_match_status(channel, "idle", state, app_handle);
-            return true;
+        // Player kicked by the referee
+        if let Some(captures) = static_regex!(r"^Kicked (.+) from the match\.?$").captures(text) {
+            return Some(LobbyEvent::PlayerKicked {
+                username: captures.get(1).unwrap().as_str().to_string(),
+            });
         }
 
-        // Room name updated
-        if let Some(captures) = static_regex!(r#"^Room name updated to "(.+)"$"#).captures(text) {
-            let room_name = captures.get(1).unwrap().as_str().to_string();
-            Self::update_lobby_settings(
-                channel,
-                |settings| {
-                    settings.room_name = room_name.clone();
-                },
-                state,
-                app_handle,
-            );
-            return true;
+        // Player finished playing with score
+        if let Some(captures) =
+            static_regex!(r"^(.+) finished playing \(Score: (\d+), (PASSED|FAILED)\)\.?$")
+                .captures(text)
+        {
+            if let Ok(score) = captures.get(2).unwrap().as_str().parse::<u64>() {
+                return Some(LobbyEvent::PlayerFinished {
+                    username: captures.get(1).unwrap().as_str().to_string(),
+                    score,
+                    passed: captures.get(3).unwrap().as_str() == "PASSED",
+                });
+            }
         }
 
-        // Mods changed (freemod disabled)
-        if let Some(captures) = static_regex!(r"^Enabled (.+), disabled FreeMod$").captures(text) {
-            let mods = captures
-                .get(1)
-                .unwrap()
-                .as_str()
-                .split(", ")
-                .map(|m| Self::normalize_mod_name(m))
-                .collect();
-            Self::update_mods(channel, mods, false, state, app_handle);
-            return true;
+        // Reply to !roll, BanchoBot literally says "point(s)"
+        if let Some(captures) =
+            static_regex!(r"^(.+) rolls (\d+) (?:points?|point\(s\))$").captures(text)
+        {
+            if let Ok(value) = captures.get(2).unwrap().as_str().parse::<u64>() {
+                return Some(LobbyEvent::PlayerRolled {
+                    username: captures.get(1).unwrap().as_str().to_string(),
+                    value,
+                });
+            }
         }
 
-        // Player changed team
-        if let Some(captures) = static_regex!(r#"^(.+) changed to (Red|Blue)$"#).captures(text) {
-            let username = captures.get(1).unwrap().as_str();
-            let team = captures.get(2).unwrap().as_str().to_lowercase();
-            let mut irc_state = state.lock().unwrap();
-            let active_room_id = irc_state.active_room_id.clone();
-            if let Some(room) = irc_state.rooms.get_mut(channel.as_str()) {
-                if let Some(lobby) = &mut room.lobby_state {
-                    for slot in &mut lobby.slots {
-                        if let Some(ref mut player) = slot.player {
-                            if player.username == username {
-                                player.team = Some(team.clone());
-                            }
-                        }
-                    }
-                    Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-                }
-            }
-            return true;
+        // Room name updated
+        if let Some(captures) = static_regex!(r#"^Room name updated to "(.+)"$"#).captures(text) {
+            return Some(LobbyEvent::RoomNameChanged {
+                name: captures.get(1).unwrap().as_str().to_string(),
+            });
         }
 
-        if text == "Disabled all mods, enabled FreeMod" {
-            Self::update_mods(channel, Vec::new(), true, state, app_handle);
-            return true;
+        // Mods changed, freemod can come along with them (e.g. "!mp mods NF Freemod")
+        if let Some(captures) =
+            static_regex!(r"^Enabled (.+), (enabled|disabled) FreeMod$").captures(text)
+        {
+            return Some(LobbyEvent::ModsChanged {
+                mods: captures
+                    .get(1)
+                    .unwrap()
+                    .as_str()
+                    .split(", ")
+                    .map(Self::normalize_mod_name)
+                    .collect(),
+                freemod: captures.get(2).unwrap().as_str() == "enabled",
+            });
         }
 
-        if text == "Disabled all mods, disabled FreeMod" {
-            Self::update_mods(channel, Vec::new(), false, state, app_handle);
-            return true;
+        // Player changed team
+        if let Some(captures) = static_regex!(r#"^(.+) changed to (Red|Blue)$"#).captures(text) {
+            return Some(LobbyEvent::PlayerChangedTeam {
+                username: captures.get(1).unwrap().as_str().to_string(),
+                team: captures.get(2).unwrap().as_str().to_lowercase(),
+            });
         }
 
         // Countdown with only seconds
         if let Some(captures) = static_regex!(r"^Countdown ends in (\d+) seconds$").captures(text) {
-            if let Ok(duration) = captures.get(1).unwrap().as_str().parse::<u32>() {
-                Self::update_timer(channel, Some(duration), state, app_handle);
-                return true;
+            if let Ok(seconds) = captures.get(1).unwrap().as_str().parse::<u32>() {
+                return Some(LobbyEvent::CountdownStarted { seconds });
             }
         }
 
@@ -400,263 +469,65 @@ impl BanchoBotParser {
             let mins = captures.get(1).unwrap().as_str().parse::<u32>();
             let secs = captures.get(2).unwrap().as_str().parse::<u32>();
             if let (Ok(m), Ok(s)) = (mins, secs) {
-                Self::update_timer(channel, Some(m * 60 + s), state, app_handle);
-                return true;
+                return Some(LobbyEvent::CountdownStarted {
+                    seconds: m * 60 + s,
+                });
             }
         }
 
-        // Countdown finished or aborted
-        if text == "Countdown finished" || text == "Countdown aborted" {
-            Self::update_timer(channel, None, state, app_handle);
-            return true;
-        }
-
-        false
+        None
     }
 
-    fn parse_slot_info(
-        slot_text: &str,
-        slot_id: u8,
-        channel: &str,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) {
-        let is_ready = !slot_text.contains("Not Ready") && !slot_text.contains("No Map");
-
-        if let Some(captures) =
-            static_regex!(r"https?://osu\.ppy\.sh/u/\d+\s+([^\s\[]+)").captures(slot_text)
-        {
-            let username = captures.get(1).unwrap().as_str().trim();
-            if !username.is_empty() {
-                let team = if slot_text.contains("Team Blue") {
-                    Some("blue".to_string())
-                } else if slot_text.contains("Team Red") {
-                    Some("red".to_string())
-                } else {
-                    None
-                };
-
-                Self::add_player(
-                    channel,
-                    slot_id,
-                    Player {
-                        username: username.to_string(),
-                        team,
-                        is_ready,
-                        is_playing: false,
-                        is_host: slot_text.contains("[Host"),
-                    },
-                    state,
-                    app_handle,
-                );
-            }
-        }
+    fn parse_slot_info(slot_text: &str) -> Option<Player> {
+        let no_map = slot_text.contains("No Map");
+        let is_ready = !slot_text.contains("Not Ready") && !no_map;
+
+        let captures =
+            static_regex!(r"https?://osu\.ppy\.sh/u/\d+\s+([^\s\[]+)").captures(slot_text)?;
+        let username = captures.get(1).unwrap().as_str().trim();
+        if username.is_empty() {
+            return None;
+        }
+
+        let team = if slot_text.contains("Team Blue") {
+            Some("blue".to_string())
+        } else if slot_text.contains("Team Red") {
+            Some("red".to_string())
+        } else {
+            None
+        };
+
+        Some(Player {
+            username: username.to_string(),
+            team,
+            is_ready,
+            is_playing: false,
+            is_host: slot_text.contains("[Host"),
+            no_map,
+            did_not_finish: false,
+        })
     }
 
-    fn update_lobby_settings<F>(
-        channel: &str,
-        updater: F,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) where
-        F: FnOnce(&mut LobbySettings),
-    {
-        let mut irc_state = state.lock().unwrap();
-        let active_room_id = irc_state.active_room_id.clone();
-        if let Some(room) = irc_state.rooms.get_mut(channel) {
-            if let Some(lobby) = &mut room.lobby_state {
-                if lobby.settings.is_none() {
-                    lobby.settings = Some(LobbySettings {
-                        room_name: String::new(),
-                        team_mode: "HeadToHead".to_string(),
-                        win_condition: "Score".to_string(),
-                        size: 16,
-                        password: None,
-                    });
-                }
-
-                if let Some(ref mut settings) = lobby.settings {
-                    updater(settings);
-                }
-
-                Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-            }
-        }
-    }
-
-    fn update_current_map(
-        channel: &str,
-        map: CurrentMap,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) {
-        let mut irc_state = state.lock().unwrap();
-        let active_room_id = irc_state.active_room_id.clone();
-        if let Some(room) = irc_state.rooms.get_mut(channel) {
-            if let Some(lobby) = &mut room.lobby_state {
-                lobby.current_map = Some(map);
-                Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-            }
-        }
-    }
-
-    fn add_player(
-        channel: &str,
-        slot_id: u8,
-        player: Player,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) {
-        let mut irc_state = state.lock().unwrap();
-        let active_room_id = irc_state.active_room_id.clone();
-        if let Some(room) = irc_state.rooms.get_mut(channel) {
-            if let Some(lobby) = &mut room.lobby_state {
-                if let Some(slot) = lobby.slots.iter_mut().find(|s| s.id == slot_id) {
-                    slot.player = Some(player);
-                    Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-                }
-            }
-        }
-    }
-
-    fn remove_player_by_username(
-        username: &str,
-        channel: &str,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) {
-        let mut irc_state = state.lock().unwrap();
-        let active_room_id = irc_state.active_room_id.clone();
-        if let Some(room) = irc_state.rooms.get_mut(channel) {
-            if let Some(lobby) = &mut room.lobby_state {
-                for slot in &mut lobby.slots {
-                    if let Some(ref player) = slot.player {
-                        if player.username == username {
-                            slot.player = None;
-                            break;
-                        }
-                    }
-                }
-                Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-            }
-        }
-    }
-
-    fn update_match_status(
-        channel: &str,
-        status: &str,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) {
-        let mut irc_state = state.lock().unwrap();
-        let active_room_id = irc_state.active_room_id.clone();
-        if let Some(room) = irc_state.rooms.get_mut(channel) {
-            if let Some(lobby) = &mut room.lobby_state {
-                lobby.match_status = status.to_string();
-
-                match status {
-                    "active" => {
-                        lobby.match_start_time = Some(
-                            SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_secs(),
-                        );
-                    }
-                    "idle" => {
-                        lobby.match_start_time = None;
-                    }
-                    "ready" => {
-                        for slot in &mut lobby.slots {
-                            if let Some(ref mut player) = slot.player {
-                                player.is_ready = true;
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-
-                Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-            }
-        }
-    }
-
-    fn clear_host(channel: &str, state: &IrcState, app_handle: &tauri::AppHandle) {
-        let mut irc_state = state.lock().unwrap();
-        let active_room_id = irc_state.active_room_id.clone();
-        if let Some(room) = irc_state.rooms.get_mut(channel) {
-            if let Some(lobby) = &mut room.lobby_state {
-                lobby.host = None;
-
-                for slot in &mut lobby.slots {
-                    if let Some(ref mut player) = slot.player {
-                        player.is_host = false;
-                    }
-                }
-
-                Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-            }
-        }
-    }
-
-    fn update_host(
-        channel: &str,
-        host_username: &str,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) {
-        let mut irc_state = state.lock().unwrap();
-        let active_room_id = irc_state.active_room_id.clone();
-        if let Some(room) = irc_state.rooms.get_mut(channel) {
-            if let Some(lobby) = &mut room.lobby_state {
-                lobby.host = Some(host_username.to_string());
-
-                for slot in &mut lobby.slots {
-                    if let Some(ref mut player) = slot.player {
-                        player.is_host = player.username == host_username;
-                    }
-                }
-
-                Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-            }
+    fn normalize_team_mode(team_mode: &str) -> String {
+        match team_mode {
+            "Head To Head" | "HeadToHead" => "HeadToHead",
+            "Tag Coop" | "TagCoop" => "TagCoop",
+            "Team Vs" | "TeamVs" => "TeamVs",
+            "Tag Team Vs" | "TagTeamVs" => "TagTeamVs",
+            _ => "HeadToHead",
         }
+        .to_string()
     }
 
-    fn move_player_to_slot(
-        channel: &str,
-        username: &str,
-        new_slot_id: u8,
-        team: Option<String>,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) {
-        let mut irc_state = state.lock().unwrap();
-        let active_room_id = irc_state.active_room_id.clone();
-        if let Some(room) = irc_state.rooms.get_mut(channel) {
-            if let Some(lobby) = &mut room.lobby_state {
-                let mut player_data = None;
-                for slot in &mut lobby.slots {
-                    if let Some(ref player) = slot.player {
-                        if player.username == username {
-                            player_data = slot.player.take();
-                            break;
-                        }
-                    }
-                }
-
-                if let Some(mut player) = player_data {
-                    if let Some(team) = team {
-                        player.team = Some(team);
-                    }
-
-                    if let Some(slot) = lobby.slots.iter_mut().find(|s| s.id == new_slot_id) {
-                        slot.player = Some(player);
-                    }
-                }
-
-                Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-            }
+    fn normalize_win_condition(win_condition: &str) -> String {
+        match win_condition {
+            "Score" => "Score",
+            "Accuracy" => "Accuracy",
+            "Combo" => "Combo",
+            "Score V2" | "ScoreV2" => "ScoreV2",
+            _ => "Score",
         }
+        .to_string()
     }
 
     fn normalize_mod_name(mod_name: &str) -> String {
@@ -677,54 +548,226 @@ impl BanchoBotParser {
         }
         .to_string()
     }
+}
 
-    fn update_mods(
-        channel: &str,
-        mods: Vec<String>,
-        freemod: bool,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) {
-        let mut irc_state = state.lock().unwrap();
-        let active_room_id = irc_state.active_room_id.clone();
-        if let Some(room) = irc_state.rooms.get_mut(channel) {
-            if let Some(lobby) = &mut room.lobby_state {
-                lobby.selected_mods = mods;
-                lobby.freemod = freemod;
-                Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Option<LobbyEvent> {
+        BanchoBotParser::parse_line(text)
     }
 
-    /// Sets or clears the lobby countdown timer.
-    /// Pass `Some(duration_secs)` to start, `None` to clear.
-    fn update_timer(
-        channel: &str,
-        duration: Option<u32>,
-        state: &IrcState,
-        app_handle: &tauri::AppHandle,
-    ) {
-        let mut irc_state = state.lock().unwrap();
-        let active_room_id = irc_state.active_room_id.clone();
-        if let Some(room) = irc_state.rooms.get_mut(channel) {
-            if let Some(lobby) = &mut room.lobby_state {
-                match duration {
-                    Some(secs) => {
-                        lobby.timer_start_time = Some(
-                            SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_secs(),
-                        );
-                        lobby.timer_duration = Some(secs);
-                    }
-                    None => {
-                        lobby.timer_start_time = None;
-                        lobby.timer_duration = None;
-                    }
+    #[test]
+    fn parses_settings_lines() {
+        assert_eq!(
+            parse("Room name: OWC: (A) vs (B), History: https://osu.ppy.sh/mp/123"),
+            Some(LobbyEvent::RoomNameChanged {
+                name: "OWC: (A) vs (B)".to_string()
+            })
+        );
+        assert_eq!(
+            parse("Team mode: TeamVs, Win condition: ScoreV2"),
+            Some(LobbyEvent::TeamModeChanged {
+                team_mode: "TeamVs".to_string(),
+                win_condition: "ScoreV2".to_string()
+            })
+        );
+        assert_eq!(
+            parse("Active mods: Hidden, HardRock, Freemod"),
+            Some(LobbyEvent::ModsChanged {
+                mods: vec!["HD".to_string(), "HR".to_string()],
+                freemod: true
+            })
+        );
+
+        let Some(LobbyEvent::SlotUpdated { slot, player }) = parse(
+            "Slot 3  Not Ready https://osu.ppy.sh/u/2 peppy            [Host / Team Blue / Hidden]",
+        ) else {
+            panic!("slot line not parsed");
+        };
+        assert_eq!(slot, 3);
+        assert_eq!(player.username, "peppy");
+        assert_eq!(player.team.as_deref(), Some("blue"));
+        assert!(player.is_host);
+        assert!(!player.is_ready);
+    }
+
+    #[test]
+    fn parses_beatmap_lines() {
+        let expected = CurrentMap {
+            beatmap_id: 75,
+            artist: "Kenji Ninuma".to_string(),
+            title: "DISCO PRINCE".to_string(),
+            difficulty: "Normal".to_string(),
+        };
+        assert_eq!(
+            parse("Beatmap: https://osu.ppy.sh/b/75 Kenji Ninuma - DISCO PRINCE [Normal]"),
+            Some(LobbyEvent::MapReported {
+                map: expected.clone()
+            })
+        );
+        assert_eq!(
+            parse("Beatmap changed to: Kenji Ninuma - DISCO PRINCE [Normal] (https://osu.ppy.sh/b/75)"),
+            Some(LobbyEvent::MapChanged {
+                map: expected,
+                by_referee: false
+            })
+        );
+        assert!(matches!(
+            parse("Changed beatmap to https://osu.ppy.sh/b/75 Kenji Ninuma - DISCO PRINCE"),
+            Some(LobbyEvent::MapChanged { map, by_referee: true })
+                if map.beatmap_id == 75 && map.difficulty.is_empty()
+        ));
+        assert!(matches!(
+            parse("Beatmap: https://osu.ppy.sh/b/75 Kenji Ninuma - DISCO PRINCE"),
+            Some(LobbyEvent::MapReported { map }) if map.beatmap_id == 75
+        ));
+    }
+
+    #[test]
+    fn parses_player_and_match_lines() {
+        assert_eq!(
+            parse("Cookiezi joined in slot 2 for team red."),
+            Some(LobbyEvent::PlayerJoined {
+                username: "Cookiezi".to_string(),
+                slot: 2,
+                team: Some("red".to_string())
+            })
+        );
+        assert_eq!(
+            parse("Cookiezi moved to slot 5"),
+            Some(LobbyEvent::PlayerMoved {
+                username: "Cookiezi".to_string(),
+                slot: 5
+            })
+        );
+        assert_eq!(
+            parse("Cookiezi finished playing (Score: 1234567, FAILED)."),
+            Some(LobbyEvent::PlayerFinished {
+                username: "Cookiezi".to_string(),
+                score: 1234567,
+                passed: false
+            })
+        );
+        assert_eq!(
+            parse("Countdown ends in 1 minute and 30 seconds"),
+            Some(LobbyEvent::CountdownStarted { seconds: 90 })
+        );
+        assert_eq!(
+            parse("Countdown aborted"),
+            Some(LobbyEvent::CountdownEnded { aborted: true })
+        );
+        assert_eq!(
+            parse("Cookiezi rolls 42 point(s)"),
+            Some(LobbyEvent::PlayerRolled {
+                username: "Cookiezi".to_string(),
+                value: 42
+            })
+        );
+        assert_eq!(
+            parse("Cookiezi rolls 1 point"),
+            Some(LobbyEvent::PlayerRolled {
+                username: "Cookiezi".to_string(),
+                value: 1
+            })
+        );
+        assert_eq!(
+            parse("Enabled NoFail, enabled FreeMod"),
+            Some(LobbyEvent::ModsChanged {
+                mods: vec!["NF".to_string()],
+                freemod: true
+            })
+        );
+        assert_eq!(parse("Aborted the match"), Some(LobbyEvent::MatchAborted));
+        assert_eq!(parse("Closed the match"), Some(LobbyEvent::MatchClosed));
+        assert_eq!(parse("Hello everyone"), None);
+    }
+
+    #[test]
+    fn parses_room_configuration_lines() {
+        assert_eq!(
+            parse("Changed match settings to 8 slots, TeamVs, ScoreV2"),
+            Some(LobbyEvent::MatchSettingsChanged {
+                size: Some(8),
+                team_mode: "TeamVs".to_string(),
+                win_condition: "ScoreV2".to_string()
+            })
+        );
+        assert_eq!(
+            parse("Changed match settings to HeadToHead, Accuracy"),
+            Some(LobbyEvent::MatchSettingsChanged {
+                size: None,
+                team_mode: "HeadToHead".to_string(),
+                win_condition: "Accuracy".to_string()
+            })
+        );
+        assert_eq!(
+            parse("Changed match to size 4"),
+            Some(LobbyEvent::SizeChanged { size: 4 })
+        );
+        assert_eq!(
+            parse("Players: 3"),
+            Some(LobbyEvent::PlayerCountReported { count: 3 })
+        );
+        assert_eq!(
+            parse("Removed the match password"),
+            Some(LobbyEvent::PasswordChanged {
+                has_password: false
+            })
+        );
+        assert_eq!(
+            parse("Locked the match"),
+            Some(LobbyEvent::LockChanged { locked: true })
+        );
+        assert_eq!(
+            parse("Kicked Cookiezi from the match."),
+            Some(LobbyEvent::PlayerKicked {
+                username: "Cookiezi".to_string()
+            })
+        );
+        assert_eq!(
+            parse("Moved Cookiezi into slot 3"),
+            Some(LobbyEvent::PlayerMoved {
+                username: "Cookiezi".to_string(),
+                slot: 3
+            })
+        );
+    }
+
+    #[test]
+    fn parses_match_created_reply() {
+        assert_eq!(
+            BanchoBotParser::parse_match_created(
+                "Created the tournament match https://osu.ppy.sh/mp/111 OWC: (A) vs (B)"
+            ),
+            Some((111, "OWC: (A) vs (B)".to_string()))
+        );
+        assert_eq!(BanchoBotParser::parse_match_created("Hello"), None);
+    }
+
+    #[test]
+    fn only_leave_lines_are_read_from_other_users() {
+        let message = |username: &str, text: &str| IrcMessage {
+            room_id: "#mp_1".to_string(),
+            username: username.to_string(),
+            message: text.to_string(),
+            timestamp: 0,
+            is_private: false,
+        };
+
+        assert_eq!(
+            BanchoBotParser::parse(&message("Cookiezi", "The match has started!")),
+            None
+        );
+        assert_eq!(
+            BanchoBotParser::parse(&message("Cookiezi", "Cookiezi left #mp_2")),
+            Some((
+                "#mp_2".to_string(),
+                LobbyEvent::PlayerLeft {
+                    username: "Cookiezi".to_string()
                 }
-                Self::emit_lobby_update(channel, lobby, active_room_id.as_deref(), app_handle);
-            }
-        }
+            ))
+        );
     }
 }
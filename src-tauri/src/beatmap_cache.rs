@@ -0,0 +1,328 @@
+use crate::osu_api::{BeatmapData, BeatmapLookup};
+use crate::settings::{load_setting, save_setting};
+use chrono::{TimeDelta, Utc};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Maps rarely change once they are in a pool, a week is plenty.
+const DEFAULT_TTL: TimeDelta = TimeDelta::days(7);
+const TTL_SETTING: &str = "beatmap_cache_ttl_hours";
+/// The most ids osu! answers in one `/beatmaps` request.
+pub const BEATMAP_BATCH_SIZE: usize = 50;
+
+/// A cached beatmap and whether it is older than the cache's TTL.
+#[derive(Clone)]
+pub struct CachedBeatmap {
+    pub data: BeatmapData,
+    pub stale: bool,
+}
+
+/// Keeps beatmap metadata from the osu! API in the `beatmap_cache` table, so pools and
+/// lobbies don't have to fetch the same map again.
+pub struct BeatmapCache {
+    ttl: Mutex<TimeDelta>,
+}
+
+impl Default for BeatmapCache {
+    fn default() -> Self {
+        Self {
+            ttl: Mutex::new(DEFAULT_TTL),
+        }
+    }
+}
+
+impl BeatmapCache {
+    pub fn ttl(&self) -> TimeDelta {
+        *self.ttl.lock().unwrap()
+    }
+
+    /// Uses the lifetime saved in the settings, if the referee ever changed it.
+    pub async fn load_ttl(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let hours = load_setting(pool, TTL_SETTING).await?;
+        match hours.as_deref().map(str::parse::<u32>) {
+            Some(Ok(hours)) if hours > 0 => {
+                *self.ttl.lock().unwrap() = TimeDelta::hours(hours as i64);
+            }
+            Some(_) => println!("Ignoring invalid beatmap cache lifetime: {:?}", hours),
+            None => {}
+        }
+        Ok(())
+    }
+
+    pub async fn save_ttl(&self, pool: &SqlitePool, hours: u32) -> Result<(), sqlx::Error> {
+        save_setting(pool, TTL_SETTING, &hours.to_string()).await?;
+        *self.ttl.lock().unwrap() = TimeDelta::hours(hours as i64);
+        Ok(())
+    }
+
+    /// The cached entry of a beatmap, fresh or not. Stale entries still beat nothing
+    /// when osu! can't be reached.
+    pub async fn load(
+        &self,
+        pool: &SqlitePool,
+        beatmap_id: u64,
+    ) -> Result<Option<CachedBeatmap>, sqlx::Error> {
+        let row: Option<(String, i64)> =
+            sqlx::query_as("SELECT data, fetched_at FROM beatmap_cache WHERE beatmap_id = ?")
+                .bind(beatmap_id as i64)
+                .fetch_optional(pool)
+                .await?;
+
+        let Some((data, fetched_at)) = row else {
+            return Ok(None);
+        };
+        let data = match serde_json::from_str(&data) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Ignoring unreadable cache entry for {}: {}", beatmap_id, e);
+                return Ok(None);
+            }
+        };
+
+        let stale = fetched_at + self.ttl().num_seconds() <= Utc::now().timestamp();
+        Ok(Some(CachedBeatmap { data, stale }))
+    }
+
+    pub async fn store(&self, pool: &SqlitePool, beatmap: &BeatmapData) -> Result<(), sqlx::Error> {
+        let data = serde_json::to_string(beatmap).unwrap_or_default();
+        sqlx::query(
+            "INSERT INTO beatmap_cache (beatmap_id, data, fetched_at)
+             VALUES (?, ?, ?)
+             ON CONFLICT(beatmap_id) DO UPDATE SET
+               data = excluded.data,
+               fetched_at = excluded.fetched_at",
+        )
+        .bind(beatmap.id as i64)
+        .bind(data)
+        .bind(Utc::now().timestamp())
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// The ids of one `/beatmaps` request and what osu! answered.
+pub type ChunkDownload = (Vec<u64>, Result<Vec<BeatmapData>, String>);
+
+/// The ids of a batch lookup that have to be asked from osu!, each once and in chunks
+/// of at most [`BEATMAP_BATCH_SIZE`].
+pub fn download_chunks(
+    requested: &[u64],
+    cached: &HashMap<u64, CachedBeatmap>,
+    force_refresh: bool,
+) -> Vec<Vec<u64>> {
+    let mut seen = HashSet::new();
+    let missing = requested
+        .iter()
+        .copied()
+        .filter(|beatmap_id| seen.insert(*beatmap_id))
+        .filter(|beatmap_id| {
+            force_refresh || cached.get(beatmap_id).is_none_or(|entry| entry.stale)
+        })
+        .collect::<Vec<_>>();
+    missing
+        .chunks(BEATMAP_BATCH_SIZE)
+        .map(<[u64]>::to_vec)
+        .collect()
+}
+
+/// Puts a batch lookup together, one entry per requested id in request order. Ids
+/// osu! answered get the new data and ids it left out are not found. When a chunk
+/// failed, its ids fall back to their cached entries, stale or not.
+pub fn merge_lookups(
+    requested: &[u64],
+    cached: &HashMap<u64, CachedBeatmap>,
+    downloads: Vec<ChunkDownload>,
+) -> Vec<BeatmapLookup> {
+    let mut downloaded: HashMap<u64, Result<BeatmapData, String>> = HashMap::new();
+    for (chunk, response) in downloads {
+        match response {
+            Ok(beatmaps) => {
+                for beatmap in beatmaps {
+                    downloaded.insert(beatmap.id, Ok(beatmap));
+                }
+                for beatmap_id in chunk {
+                    downloaded
+                        .entry(beatmap_id)
+                        .or_insert_with(|| Err("Beatmap not found".to_string()));
+                }
+            }
+            Err(e) => {
+                for beatmap_id in chunk {
+                    let fallback = match cached.get(&beatmap_id) {
+                        Some(entry) => Ok(entry.data.clone()),
+                        None => Err(e.clone()),
+                    };
+                    downloaded.insert(beatmap_id, fallback);
+                }
+            }
+        }
+    }
+
+    requested
+        .iter()
+        .map(|&beatmap_id| {
+            let result = match downloaded.get(&beatmap_id) {
+                Some(result) => result.clone(),
+                None => cached
+                    .get(&beatmap_id)
+                    .map(|entry| entry.data.clone())
+                    .ok_or_else(|| "Beatmap not found".to_string()),
+            };
+            BeatmapLookup {
+                beatmap_id,
+                beatmap: result.as_ref().ok().cloned(),
+                error: result.err(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations::get_migrations;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    /// An in-memory database with the cache and settings tables. A single connection,
+    /// every new one would open an empty database.
+    async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        for migration in get_migrations()
+            .iter()
+            .filter(|m| ["create_beatmap_cache", "create_settings"].contains(&m.description))
+        {
+            sqlx::raw_sql(migration.sql).execute(&pool).await.unwrap();
+        }
+        pool
+    }
+
+    fn beatmap(id: u64) -> BeatmapData {
+        BeatmapData {
+            id,
+            beatmapset_id: 1,
+            artist: "Kenji Ninuma".to_string(),
+            title: "DISCO PRINCE".to_string(),
+            difficulty: "Normal".to_string(),
+            mapper: "peppy".to_string(),
+            mode: 0,
+            total_length: 142,
+            bpm: 120.0,
+            difficulty_rating: 2.4,
+        }
+    }
+
+    async fn age_entry(pool: &SqlitePool, beatmap_id: u64, age: TimeDelta) {
+        sqlx::query("UPDATE beatmap_cache SET fetched_at = ? WHERE beatmap_id = ?")
+            .bind((Utc::now() - age).timestamp())
+            .bind(beatmap_id as i64)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn entries_go_stale_after_the_ttl() {
+        let pool = test_pool().await;
+        let cache = BeatmapCache::default();
+
+        assert!(cache.load(&pool, 75).await.unwrap().is_none());
+        cache.store(&pool, &beatmap(75)).await.unwrap();
+        let cached = cache.load(&pool, 75).await.unwrap().unwrap();
+        assert_eq!(cached.data.title, "DISCO PRINCE");
+        assert!(!cached.stale);
+
+        age_entry(&pool, 75, TimeDelta::days(8)).await;
+        assert!(cache.load(&pool, 75).await.unwrap().unwrap().stale);
+
+        cache.store(&pool, &beatmap(75)).await.unwrap();
+        assert!(!cache.load(&pool, 75).await.unwrap().unwrap().stale);
+    }
+
+    #[tokio::test]
+    async fn saved_ttl_survives_a_restart() {
+        let pool = test_pool().await;
+        BeatmapCache::default().save_ttl(&pool, 720).await.unwrap();
+
+        let cache = BeatmapCache::default();
+        cache.load_ttl(&pool).await.unwrap();
+        assert_eq!(cache.ttl(), TimeDelta::days(30));
+
+        cache.store(&pool, &beatmap(75)).await.unwrap();
+        age_entry(&pool, 75, TimeDelta::days(8)).await;
+        assert!(!cache.load(&pool, 75).await.unwrap().unwrap().stale);
+    }
+
+    fn cached(beatmap_id: u64, stale: bool) -> (u64, CachedBeatmap) {
+        (
+            beatmap_id,
+            CachedBeatmap {
+                data: beatmap(beatmap_id),
+                stale,
+            },
+        )
+    }
+
+    #[test]
+    fn chunks_skip_fresh_and_duplicate_ids() {
+        let requested = (1..=120).chain([3, 3, 120]).collect::<Vec<_>>();
+        let cache = HashMap::from([cached(1, false), cached(2, true)]);
+
+        let chunks = download_chunks(&requested, &cache, false);
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![50, 50, 19]
+        );
+        assert_eq!(chunks[0][0], 2);
+        assert_eq!(chunks.concat().iter().filter(|&&id| id == 3).count(), 1);
+
+        let chunks = download_chunks(&requested, &cache, true);
+        assert_eq!(chunks.concat().len(), 120);
+    }
+
+    #[test]
+    fn merges_partial_responses_in_request_order() {
+        let requested = vec![7, 5, 7, 6];
+        let cache = HashMap::from([cached(6, false)]);
+        let downloads = vec![(vec![7, 5], Ok(vec![beatmap(5)]))];
+
+        let lookups = merge_lookups(&requested, &cache, downloads);
+        let ids = lookups.iter().map(|l| l.beatmap_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![7, 5, 7, 6]);
+        assert_eq!(lookups[0].error.as_deref(), Some("Beatmap not found"));
+        assert!(lookups[0].beatmap.is_none());
+        assert_eq!(lookups[1].beatmap.as_ref().unwrap().id, 5);
+        assert!(lookups[1].error.is_none());
+        assert_eq!(lookups[2].error.as_deref(), Some("Beatmap not found"));
+        assert_eq!(lookups[3].beatmap.as_ref().unwrap().id, 6);
+    }
+
+    #[test]
+    fn failed_chunk_falls_back_to_stale_entries() {
+        let requested = (1..=60).collect::<Vec<_>>();
+        let cache = HashMap::from([cached(10, true), cached(55, true)]);
+        let chunks = download_chunks(&requested, &cache, false);
+        let downloads = vec![
+            (
+                chunks[0].clone(),
+                Err("osu! API request failed".to_string()),
+            ),
+            (
+                chunks[1].clone(),
+                Ok(chunks[1].iter().map(|&id| beatmap(id)).collect()),
+            ),
+        ];
+
+        let lookups = merge_lookups(&requested, &cache, downloads);
+        assert_eq!(lookups.len(), 60);
+        assert_eq!(lookups[9].beatmap.as_ref().unwrap().id, 10);
+        assert!(lookups[9].error.is_none());
+        assert_eq!(lookups[0].error.as_deref(), Some("osu! API request failed"));
+        assert!(lookups[50..].iter().all(|l| l.beatmap.is_some()));
+    }
+}
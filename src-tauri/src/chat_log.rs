@@ -0,0 +1,186 @@
+use crate::database::sqlite_pool;
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+type ChatMessageRow = (String, String, String, i64, bool);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatSearchResult {
+    pub message: IrcMessage,
+    pub snippet: String,
+}
+
+/// Writes every chat message to the `chat_messages` table in the background,
+/// so the IRC handler never waits on the database.
+pub struct ChatLog {
+    sender: UnboundedSender<IrcMessage>,
+}
+
+impl ChatLog {
+    pub fn new(app_handle: tauri::AppHandle) -> Self {
+        let (sender, receiver) = unbounded_channel();
+        tauri::async_runtime::spawn(run_writer(app_handle, receiver));
+        Self { sender }
+    }
+
+    pub fn record(&self, message: &IrcMessage) {
+        let _ = self.sender.send(message.clone());
+    }
+}
+
+async fn run_writer(app_handle: tauri::AppHandle, mut receiver: UnboundedReceiver<IrcMessage>) {
+    let mut pool: Option<SqlitePool> = None;
+
+    while let Some(message) = receiver.recv().await {
+        if pool.is_none() {
+            match sqlite_pool(&app_handle).await {
+                Ok(p) => pool = Some(p),
+                Err(e) => {
+                    println!("Chat log unavailable, dropping message: {}", e);
+                    continue;
+                }
+            }
+        }
+
+        if let Some(pool) = &pool {
+            if let Err(e) = insert_message(pool, &message).await {
+                println!("Failed to store chat message: {}", e);
+            }
+        }
+    }
+}
+
+async fn insert_message(pool: &SqlitePool, message: &IrcMessage) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO chat_messages (room_id, username, message, timestamp, is_private)
+         VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(&message.room_id)
+    .bind(&message.username)
+    .bind(&message.message)
+    .bind(message.timestamp as i64)
+    .bind(message.is_private)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+fn row_to_message(row: ChatMessageRow) -> IrcMessage {
+    let (room_id, username, message, timestamp, is_private) = row;
+    IrcMessage {
+        room_id,
+        username,
+        message,
+        timestamp: timestamp as u64,
+        is_private,
+    }
+}
+
+/// Loads a page of a room's history, `offset` messages back from the newest one.
+pub async fn load_messages_page(
+    pool: &SqlitePool,
+    room_id: &str,
+    offset: usize,
+    limit: usize,
+) -> Result<MessagesPage, sqlx::Error> {
+    let mut rows: Vec<ChatMessageRow> = sqlx::query_as(
+        "SELECT room_id, username, message, timestamp, is_private
+         FROM chat_messages
+         WHERE room_id = ?
+         ORDER BY id DESC
+         LIMIT ? OFFSET ?",
+    )
+    .bind(room_id)
+    .bind(limit as i64 + 1)
+    .bind(offset as i64)
+    .fetch_all(pool)
+    .await?;
+
+    let has_more = rows.len() > limit;
+    rows.truncate(limit);
+    rows.reverse();
+
+    Ok(MessagesPage {
+        messages: rows.into_iter().map(row_to_message).collect(),
+        has_more,
+    })
+}
+
+/// Loads a room's complete history in chronological order.
+pub async fn load_room_history(
+    pool: &SqlitePool,
+    room_id: &str,
+) -> Result<Vec<IrcMessage>, sqlx::Error> {
+    let rows: Vec<ChatMessageRow> = sqlx::query_as(
+        "SELECT room_id, username, message, timestamp, is_private
+         FROM chat_messages
+         WHERE room_id = ?
+         ORDER BY id ASC",
+    )
+    .bind(room_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(row_to_message).collect())
+}
+
+/// Full-text search over all stored messages, newest matches first.
+pub async fn search_messages(
+    pool: &SqlitePool,
+    query: &str,
+    room_id: Option<&str>,
+    limit: usize,
+) -> Result<Vec<ChatSearchResult>, sqlx::Error> {
+    let fts_query = to_fts_query(query);
+    if fts_query.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let rows: Vec<(String, String, String, i64, bool, String)> = sqlx::query_as(
+        "SELECT m.room_id, m.username, m.message, m.timestamp, m.is_private,
+                snippet(chat_messages_fts, 0, '**', '**', '...', 12)
+         FROM chat_messages_fts
+         JOIN chat_messages m ON m.id = chat_messages_fts.rowid
+         WHERE chat_messages_fts MATCH ?1 AND (?2 IS NULL OR m.room_id = ?2)
+         ORDER BY m.id DESC
+         LIMIT ?3",
+    )
+    .bind(fts_query)
+    .bind(room_id)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(
+            |(room_id, username, message, timestamp, is_private, snippet)| ChatSearchResult {
+                message: row_to_message((room_id, username, message, timestamp, is_private)),
+                snippet,
+            },
+        )
+        .collect())
+}
+
+/// Quotes every search term so user input can't break the FTS5 query syntax.
+/// A trailing `*` is kept to allow prefix searches.
+fn to_fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .filter_map(|term| {
+            let (term, prefix) = match term.strip_suffix('*') {
+                Some(stripped) => (stripped, "*"),
+                None => (term, ""),
+            };
+            if term.is_empty() {
+                return None;
+            }
+            Some(format!("\"{}\"{}", term.replace('"', "\"\""), prefix))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -1,10 +1,28 @@
-use crate::irc_handler::handle_irc_connection;
+use crate::auto_ref::AutoRefConfig;
+use crate::beatmap_cache::{download_chunks, merge_lookups, BeatmapCache};
+use crate::chat_log::{load_messages_page, load_room_history, search_messages, ChatSearchResult};
+use crate::credentials::{self, UserCredentials};
+use crate::database::sqlite_pool;
+use crate::draft::{save_draft, DraftActionKind, DraftConfig, MatchDraft, SavedDraft};
+use crate::irc_handler::{connect_client, rejoin_rooms, supervise_irc_connection};
+use crate::mappool::{self, BeatmapEntry, Mappool, NewBeatmapEntry, SelectedSlot};
+use crate::match_export::{ExportFormat, MatchLog};
+use crate::match_history::{self, MatchHistoryEntry, MatchHistorySummary};
 use crate::osu_api::*;
+use crate::osu_client::OsuApiClient;
+use crate::qualifier::QualifierRun;
+use crate::secrets::SecretStore;
+use crate::send_queue::{RateLimitConfig, SendPriority};
 use crate::types::*;
 use anyhow::Result;
-use irc::client::prelude::*;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tauri::{Emitter, State};
+use tauri::{Emitter, Manager, State};
+
+const LOBBY_CREATION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+const LOBBY_JOIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+const MAP_CHANGE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
 fn emit_rooms_list_updated(app_handle: &tauri::AppHandle, state: &IrcState) {
     let rooms_response = {
@@ -31,70 +49,40 @@ pub async fn connect_to_bancho(
         if irc_state.connected {
             return Err("Already connected to IRC".to_string());
         }
+        if irc_state.reconnecting {
+            return Err("Reconnect already in progress".to_string());
+        }
     }
 
-    let irc_config = Config {
-        nickname: Some(config.username.clone()),
-        server: Some("irc.ppy.sh".to_string()),
-        port: Some(6667),
-        username: Some(config.username.clone()),
-        password: Some(config.password.clone()),
-        use_tls: Some(false),
-        ..Config::default()
-    };
+    let client = connect_client(&config).await.map_err(|e| {
+        println!("Failed to create IRC client: {}", e);
+        e
+    })?;
 
-    match irc::client::Client::from_config(irc_config).await {
-        Ok(client) => {
-            println!("IRC client created successfully");
+    println!("Connected to osu! Bancho!");
 
-            if let Err(e) = client.identify() {
-                return Err(format!("Failed to identify: {}", e));
-            }
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<IrcCommand>();
 
-            println!("Connected to osu! Bancho!");
-
-            let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<IrcCommand>();
+    {
+        let mut irc_state = state.lock().unwrap();
+        irc_state.connected = true;
+        irc_state.reconnecting = false;
+        irc_state.config = Some(config.clone());
+        irc_state.current_username = Some(config.username.clone());
+        irc_state.message_sender = Some(tx);
+    }
 
-            {
-                let mut irc_state = state.lock().unwrap();
-                irc_state.connected = true;
-                irc_state.config = Some(config.clone());
-                irc_state.current_username = Some(config.username.clone());
-                irc_state.message_sender = Some(tx);
-            }
+    let state_clone = Arc::clone(&state.inner());
+    let app_handle_clone = app_handle.clone();
 
-            let state_clone = Arc::clone(&state.inner());
-            let app_handle_clone = app_handle.clone();
+    tokio::spawn(async move {
+        supervise_irc_connection(client, config, app_handle_clone, state_clone, rx).await;
+    });
 
-            tokio::spawn(async move {
-                handle_irc_connection(client, app_handle_clone, state_clone, rx).await;
-            });
+    // Rejoin all previously joined channels and multiplayer lobbies
+    rejoin_rooms(&state);
 
-            // Rejoin all previously joined channels and multiplayer lobbies
-            {
-                let irc_state = state.lock().unwrap();
-                let message_sender = irc_state.message_sender.clone();
-                let rooms_to_rejoin: Vec<String> = irc_state
-                    .rooms
-                    .iter()
-                    .filter_map(|(room_id, room)| match room.room_type {
-                        RoomType::Channel | RoomType::MultiplayerLobby => Some(room_id.clone()),
-                        _ => None,
-                    })
-                    .collect();
-                if let Some(sender) = message_sender {
-                    for room_id in rooms_to_rejoin {
-                        let _ = sender.send(IrcCommand::JoinChannel { channel: room_id });
-                    }
-                }
-            }
-            Ok("Successfully connected to osu! Bancho".to_string())
-        }
-        Err(e) => {
-            println!("Failed to create IRC client: {}", e);
-            Err(format!("Failed to connect: {}", e))
-        }
-    }
+    Ok("Successfully connected to osu! Bancho".to_string())
 }
 
 #[tauri::command]
@@ -118,12 +106,28 @@ pub async fn send_message_to_room(
         };
 
         if let Some(room) = room {
+            if room.closed {
+                return Err("This lobby is closed".to_string());
+            }
             let command = match room.room_type {
                 RoomType::Channel | RoomType::MultiplayerLobby => {
                     if message.trim() == "!mp settings" {
                         clear_lobby_state(&room_id, &state);
                     }
-                    IrcCommand::SendMessage { room_id, message }
+                    if message.trim_start().starts_with("!mp start") {
+                        if let Some(reason) = room
+                            .lobby_state
+                            .as_ref()
+                            .and_then(|lobby| lobby.start_blocked())
+                        {
+                            return Err(reason);
+                        }
+                    }
+                    IrcCommand::SendMessage {
+                        priority: SendPriority::of(&message),
+                        room_id,
+                        message,
+                    }
                 }
                 RoomType::PrivateMessage => IrcCommand::SendPrivateMessage {
                     username: room_id,
@@ -173,7 +177,25 @@ pub async fn join_channel(room_id: String, state: State<'_, IrcState>) -> Result
 }
 
 #[tauri::command]
-pub async fn leave_channel(room_id: String, state: State<'_, IrcState>) -> Result<String, String> {
+pub async fn leave_channel(
+    room_id: String,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<String, String> {
+    // Bancho already parted us from a closed lobby, only our copy is left
+    let is_closed = {
+        let irc_state = state.lock().unwrap();
+        irc_state
+            .rooms
+            .get(&room_id)
+            .is_some_and(|room| room.closed)
+    };
+    if is_closed {
+        remove_room(&room_id, &state);
+        emit_rooms_list_updated(&app_handle, &state);
+        return Ok(format!("Closed lobby: {}", room_id));
+    }
+
     let sender = {
         let irc_state = state.lock().unwrap();
         if !irc_state.connected {
@@ -219,6 +241,9 @@ pub async fn reconnect_to_bancho(
         if irc_state.connected {
             return Ok("Already connected".to_string());
         }
+        if irc_state.reconnecting {
+            return Ok("Reconnect already in progress".to_string());
+        }
         irc_state.config.clone()
     };
 
@@ -236,7 +261,7 @@ pub async fn disconnect_from_bancho(
 ) -> Result<String, String> {
     let sender = {
         let irc_state = state.lock().unwrap();
-        if !irc_state.connected {
+        if !irc_state.connected && !irc_state.reconnecting {
             return Err("Not connected".to_string());
         }
         irc_state.message_sender.clone()
@@ -260,6 +285,30 @@ pub async fn disconnect_from_bancho(
     Ok("Disconnected from osu! Bancho".to_string())
 }
 
+#[tauri::command]
+pub async fn set_rate_limit(
+    config: RateLimitConfig,
+    state: State<'_, IrcState>,
+) -> Result<RateLimitConfig, String> {
+    if config.burst == 0 || config.messages_per_second <= 0.0 {
+        return Err("Rate limit must allow at least one message".to_string());
+    }
+
+    let sender = {
+        let mut irc_state = state.lock().unwrap();
+        irc_state.rate_limit = config.clone();
+        irc_state.message_sender.clone()
+    };
+
+    if let Some(sender) = sender {
+        let _ = sender.send(IrcCommand::SetRateLimit {
+            config: config.clone(),
+        });
+    }
+
+    Ok(config)
+}
+
 #[tauri::command]
 pub async fn get_connection_status(state: State<'_, IrcState>) -> Result<bool, String> {
     let irc_state = state.lock().unwrap();
@@ -319,7 +368,17 @@ pub async fn get_room_messages_page(
     offset: usize,
     limit: usize,
     state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
 ) -> Result<MessagesPage, String> {
+    match sqlite_pool(&app_handle).await {
+        Ok(pool) => match load_messages_page(&pool, &room_id, offset, limit).await {
+            Ok(page) => return Ok(page),
+            Err(e) => println!("Failed to load messages from chat log: {}", e),
+        },
+        Err(e) => println!("Chat log unavailable: {}", e),
+    }
+
+    // Fall back to the in-memory history
     let irc_state = state.lock().unwrap();
     if let Some(room) = irc_state.rooms.get(&room_id) {
         Ok(room.get_messages_page(offset, limit))
@@ -328,6 +387,67 @@ pub async fn get_room_messages_page(
     }
 }
 
+#[tauri::command]
+pub async fn search_chat_messages(
+    query: String,
+    room_id: Option<String>,
+    limit: Option<usize>,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<ChatSearchResult>, String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    search_messages(&pool, &query, room_id.as_deref(), limit.unwrap_or(50))
+        .await
+        .map_err(|e| format!("Failed to search messages: {}", e))
+}
+
+#[tauri::command]
+pub async fn export_match_log(
+    room_id: String,
+    format: ExportFormat,
+    path: Option<String>,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<String, String> {
+    let (lobby_state, in_memory_messages) = {
+        let irc_state = state.lock().unwrap();
+        let room = irc_state
+            .rooms
+            .get(&room_id)
+            .ok_or_else(|| "Room not found".to_string())?;
+        (room.lobby_state.clone(), room.messages.clone())
+    };
+
+    // Prefer the full history on disk, the in-memory one may have been trimmed
+    let messages = match sqlite_pool(&app_handle).await {
+        Ok(pool) => match load_room_history(&pool, &room_id).await {
+            Ok(messages) if !messages.is_empty() => messages,
+            Ok(_) => in_memory_messages,
+            Err(e) => {
+                println!("Failed to load chat log for export: {}", e);
+                in_memory_messages
+            }
+        },
+        Err(_) => in_memory_messages,
+    };
+
+    let match_log = MatchLog::new(room_id, lobby_state, messages);
+    let contents = match_log.render(format)?;
+
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => app_handle
+            .path()
+            .download_dir()
+            .or_else(|_| app_handle.path().app_data_dir())
+            .map_err(|e| format!("Failed to resolve export directory: {}", e))?
+            .join(match_log.file_name(format)),
+    };
+
+    std::fs::write(&path, contents).map_err(|e| format!("Failed to write match log: {}", e))?;
+
+    Ok(path.to_string_lossy().to_string())
+}
+
 #[tauri::command]
 pub async fn start_private_message(
     username: String,
@@ -354,6 +474,122 @@ pub async fn start_private_message(
     Ok(format!("Started private message with {}", username))
 }
 
+/// Creates a tournament lobby with `!mp make`, waits for BanchoBot to confirm it and
+/// applies the initial settings. Returns the new room id, e.g. `#mp_123456`.
+#[tauri::command]
+pub async fn create_lobby(
+    options: CreateLobbyOptions,
+    state: State<'_, IrcState>,
+) -> Result<String, String> {
+    let name = options.name.trim().to_string();
+    if name.is_empty() {
+        return Err("Lobby name cannot be empty".to_string());
+    }
+    if let Some(size) = options.size {
+        if !(1..=MAX_LOBBY_SLOTS).contains(&size) {
+            return Err(format!(
+                "Lobby size must be between 1 and {}",
+                MAX_LOBBY_SLOTS
+            ));
+        }
+    }
+    let set_command = lobby_set_command(&options)?;
+
+    let (created_tx, created_rx) = tokio::sync::oneshot::channel();
+    let sender = {
+        let mut irc_state = state.lock().unwrap();
+        if !irc_state.connected {
+            return Err("Not connected to IRC".to_string());
+        }
+        let sender = irc_state
+            .message_sender
+            .clone()
+            .ok_or("Message sender not available")?;
+        irc_state.pending_lobbies.push(PendingLobby {
+            name: name.clone(),
+            created: created_tx,
+        });
+        sender
+    };
+
+    sender
+        .send(IrcCommand::SendPrivateMessage {
+            username: "BanchoBot".to_string(),
+            message: format!("!mp make {}", name),
+        })
+        .map_err(|_| "Failed to queue message for sending".to_string())?;
+
+    let match_id = match tokio::time::timeout(LOBBY_CREATION_TIMEOUT, created_rx).await {
+        Ok(Ok(match_id)) => match_id,
+        Ok(Err(_)) => return Err("Lobby creation was cancelled".to_string()),
+        Err(_) => {
+            let mut irc_state = state.lock().unwrap();
+            irc_state
+                .pending_lobbies
+                .retain(|pending| pending.name != name);
+            return Err("BanchoBot did not confirm the new lobby in time".to_string());
+        }
+    };
+    let room_id = format!("#mp_{}", match_id);
+
+    // Bancho joins us to the new channel on its own, wait for that before configuring it
+    let deadline = tokio::time::Instant::now() + LOBBY_JOIN_TIMEOUT;
+    while !state.lock().unwrap().rooms.contains_key(&room_id) {
+        if tokio::time::Instant::now() > deadline {
+            return Err(format!("Created {} but failed to join it", room_id));
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    let mut setup_messages = Vec::new();
+    if let Some(set_command) = set_command {
+        setup_messages.push(set_command);
+        // Refresh the lobby state with the settings we just applied
+        setup_messages.push("!mp settings".to_string());
+    }
+    if let Some(password) = options.password.filter(|p| !p.is_empty()) {
+        setup_messages.push(format!("!mp password {}", password));
+    }
+    for message in setup_messages {
+        sender
+            .send(IrcCommand::SendMessage {
+                room_id: room_id.clone(),
+                message,
+                priority: SendPriority::Normal,
+            })
+            .map_err(|_| "Failed to queue message for sending".to_string())?;
+    }
+
+    Ok(room_id)
+}
+
+/// Builds the `!mp set` (or `!mp size`) command for the requested lobby settings.
+fn lobby_set_command(options: &CreateLobbyOptions) -> Result<Option<String>, String> {
+    if options.team_mode.is_none() && options.win_condition.is_none() {
+        return Ok(options.size.map(|size| format!("!mp size {}", size)));
+    }
+
+    let team_mode = match options.team_mode.as_deref().unwrap_or("HeadToHead") {
+        "HeadToHead" => 0,
+        "TagCoop" => 1,
+        "TeamVs" => 2,
+        "TagTeamVs" => 3,
+        other => return Err(format!("Unknown team mode: {}", other)),
+    };
+    let win_condition = match options.win_condition.as_deref().unwrap_or("Score") {
+        "Score" => 0,
+        "Accuracy" => 1,
+        "Combo" => 2,
+        "ScoreV2" => 3,
+        other => return Err(format!("Unknown win condition: {}", other)),
+    };
+
+    Ok(Some(match options.size {
+        Some(size) => format!("!mp set {} {} {}", team_mode, win_condition, size),
+        None => format!("!mp set {} {}", team_mode, win_condition),
+    }))
+}
+
 #[tauri::command]
 pub async fn set_mappool(
     room_id: String,
@@ -386,60 +622,804 @@ pub async fn set_map_drain_time(
     Err("Lobby not found".to_string())
 }
 
+/// Clears the captain rolls so they can roll again.
+#[tauri::command]
+pub async fn reset_rolls(room_id: String, state: State<'_, IrcState>) -> Result<RollState, String> {
+    let mut irc_state = state.lock().unwrap();
+    if let Some(room) = irc_state.rooms.get_mut(&room_id) {
+        if let Some(lobby) = &mut room.lobby_state {
+            lobby.rolls = RollState::default();
+            return Ok(lobby.rolls.clone());
+        }
+    }
+    Err("Lobby not found".to_string())
+}
+
+/// Stops checking the lobby against its selected pool slot, which also lifts the
+/// block on `!mp start`.
+#[tauri::command]
+pub async fn clear_selected_slot(
+    room_id: String,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let slot_code = {
+        let mut irc_state = state.lock().unwrap();
+        let lobby = irc_state
+            .rooms
+            .get_mut(&room_id)
+            .and_then(|room| room.lobby_state.as_mut())
+            .ok_or("Lobby not found")?;
+        lobby.slot_mismatches.clear();
+        lobby.selected_slot.take().map(|slot| slot.slot_code)
+    };
+
+    if let Some(slot_code) = slot_code {
+        let _ = app_handle.emit(
+            "slot-mismatch",
+            serde_json::json!({
+                "roomId": room_id,
+                "slotCode": slot_code,
+                "mismatches": Vec::<String>::new()
+            }),
+        );
+    }
+    Ok(())
+}
+
+/// Turns auto-ref on for a lobby, or off when `config` is `None`.
+#[tauri::command]
+pub async fn set_auto_ref(
+    room_id: String,
+    config: Option<AutoRefConfig>,
+    state: State<'_, IrcState>,
+) -> Result<(), String> {
+    if let Some(config) = &config {
+        if config.start_delay > 300 || config.ready_timer.is_some_and(|t| t == 0 || t > 300) {
+            return Err("Timers must be between 1 and 300 seconds".to_string());
+        }
+    }
+
+    let mut irc_state = state.lock().unwrap();
+    if let Some(room) = irc_state.rooms.get_mut(&room_id) {
+        if let Some(lobby) = &mut room.lobby_state {
+            lobby.auto_ref = config;
+            return Ok(());
+        }
+    }
+    Err("Lobby not found".to_string())
+}
+
+#[tauri::command]
+pub async fn set_match_best_of(
+    room_id: String,
+    best_of: Option<u32>,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<MatchScoreline, String> {
+    let (scoreline, saved) = {
+        let mut irc_state = state.lock().unwrap();
+        let lobby = irc_state
+            .rooms
+            .get_mut(&room_id)
+            .and_then(|room| room.lobby_state.as_mut())
+            .ok_or("Lobby not found")?;
+        lobby.scoreline.set_best_of(best_of);
+        (lobby.scoreline.clone(), lobby.saved_draft())
+    };
+
+    save_scoreline(&room_id, saved, &app_handle).await?;
+    Ok(scoreline)
+}
+
+#[tauri::command]
+pub async fn set_map_winner(
+    room_id: String,
+    map_index: u32,
+    winner: Option<String>,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<MatchScoreline, String> {
+    if let Some(team) = winner.as_deref() {
+        if team != "red" && team != "blue" {
+            return Err(format!("Invalid team: {}", team));
+        }
+    }
+
+    let (scoreline, saved) = {
+        let mut irc_state = state.lock().unwrap();
+        let lobby = irc_state
+            .rooms
+            .get_mut(&room_id)
+            .and_then(|room| room.lobby_state.as_mut())
+            .ok_or("Lobby not found")?;
+        if !lobby.scoreline.set_map_winner(map_index, winner) {
+            return Err("Map not found in scoreline".to_string());
+        }
+        (lobby.scoreline.clone(), lobby.saved_draft())
+    };
+
+    save_scoreline(&room_id, saved, &app_handle).await?;
+    Ok(scoreline)
+}
+
+#[tauri::command]
+pub async fn configure_draft(
+    room_id: String,
+    config: DraftConfig,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<MatchDraft, String> {
+    let draft = MatchDraft::new(config)?;
+    update_draft(&room_id, &state, &app_handle, |lobby| {
+        lobby.draft = Some(draft);
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn record_draft_action(
+    room_id: String,
+    kind: DraftActionKind,
+    team: String,
+    beatmap_id: u64,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<MatchDraft, String> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    update_draft(&room_id, &state, &app_handle, |lobby| {
+        let draft = lobby
+            .draft
+            .as_mut()
+            .ok_or("No draft configured for this lobby")?;
+        draft.record(kind, &team, beatmap_id, timestamp, &lobby.scoreline)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn undo_draft_action(
+    room_id: String,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<MatchDraft, String> {
+    update_draft(&room_id, &state, &app_handle, |lobby| {
+        let draft = lobby
+            .draft
+            .as_mut()
+            .ok_or("No draft configured for this lobby")?;
+        draft.undo().ok_or("Nothing to undo")?;
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn clear_draft(
+    room_id: String,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    {
+        let mut irc_state = state.lock().unwrap();
+        let lobby = irc_state
+            .rooms
+            .get_mut(&room_id)
+            .and_then(|room| room.lobby_state.as_mut())
+            .ok_or("Lobby not found")?;
+        lobby.draft = None;
+    }
+
+    let pool = sqlite_pool(&app_handle).await?;
+    save_draft(&pool, &room_id, None)
+        .await
+        .map_err(|e| format!("Failed to delete draft: {}", e))
+}
+
+#[tauri::command]
+pub async fn get_mappools(app_handle: tauri::AppHandle) -> Result<Vec<Mappool>, String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    mappool::list_mappools(&pool)
+        .await
+        .map_err(|e| format!("Failed to load mappools: {}", e))
+}
+
+#[tauri::command]
+pub async fn create_mappool(
+    name: String,
+    description: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<i64, String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    mappool::create_mappool(&pool, &name, description)
+        .await
+        .map_err(|e| format!("Failed to create mappool: {}", e))
+}
+
+#[tauri::command]
+pub async fn update_mappool(
+    id: i64,
+    name: String,
+    description: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    mappool::update_mappool(&pool, id, &name, description)
+        .await
+        .map_err(|e| format!("Failed to update mappool: {}", e))
+}
+
+#[tauri::command]
+pub async fn delete_mappool(id: i64, app_handle: tauri::AppHandle) -> Result<(), String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    mappool::delete_mappool(&pool, id)
+        .await
+        .map_err(|e| format!("Failed to delete mappool: {}", e))
+}
+
+#[tauri::command]
+pub async fn import_mappool(
+    name: String,
+    entries: Vec<NewBeatmapEntry>,
+    app_handle: tauri::AppHandle,
+) -> Result<i64, String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    mappool::import_mappool(&pool, &name, entries)
+        .await
+        .map_err(|e| format!("Failed to import mappool: {}", e))
+}
+
+#[tauri::command]
+pub async fn get_mappool_beatmaps(
+    mappool_id: i64,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<BeatmapEntry>, String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    mappool::pool_beatmaps(&pool, mappool_id)
+        .await
+        .map_err(|e| format!("Failed to load beatmaps: {}", e))
+}
+
+#[tauri::command]
+pub async fn add_beatmap_to_pool(
+    mappool_id: i64,
+    entry: NewBeatmapEntry,
+    app_handle: tauri::AppHandle,
+) -> Result<i64, String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    mappool::add_beatmap(&pool, mappool_id, entry)
+        .await
+        .map_err(|e| format!("Failed to add beatmap: {}", e))
+}
+
+#[tauri::command]
+pub async fn update_beatmap_in_pool(
+    id: i64,
+    category: Option<String>,
+    mod_combination: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    mappool::update_beatmap(&pool, id, category, mod_combination)
+        .await
+        .map_err(|e| format!("Failed to update beatmap: {}", e))
+}
+
+#[tauri::command]
+pub async fn reorder_beatmaps(
+    ordered_ids: Vec<i64>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    mappool::reorder_beatmaps(&pool, &ordered_ids)
+        .await
+        .map_err(|e| format!("Failed to reorder beatmaps: {}", e))
+}
+
+#[tauri::command]
+pub async fn delete_beatmap_from_pool(id: i64, app_handle: tauri::AppHandle) -> Result<(), String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    mappool::delete_beatmap(&pool, id)
+        .await
+        .map_err(|e| format!("Failed to delete beatmap: {}", e))
+}
+
+#[tauri::command]
+pub async fn get_match_history(
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<MatchHistorySummary>, String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    match_history::list_match_history(&pool)
+        .await
+        .map_err(|e| format!("Failed to load match history: {}", e))
+}
+
+#[tauri::command]
+pub async fn get_match_history_entry(
+    id: i64,
+    app_handle: tauri::AppHandle,
+) -> Result<MatchHistoryEntry, String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    match_history::load_match_history(&pool, id)
+        .await
+        .map_err(|e| format!("Failed to load match: {}", e))?
+        .ok_or_else(|| "Match not found".to_string())
+}
+
+#[tauri::command]
+pub async fn delete_match_history_entry(
+    id: i64,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    match_history::delete_match_history(&pool, id)
+        .await
+        .map_err(|e| format!("Failed to delete match: {}", e))
+}
+
+/// Puts a slot of the lobby's mappool up, map and mods together, and checks that
+/// BanchoBot changed to the map we asked for.
+#[tauri::command]
+pub async fn select_pool_slot(
+    room_id: String,
+    slot_code: String,
+    mode: Option<u8>,
+    no_fail: Option<bool>,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<BeatmapEntry, String> {
+    let (mappool_id, sender) = {
+        let irc_state = state.lock().unwrap();
+        if !irc_state.connected {
+            return Err("Not connected to IRC".to_string());
+        }
+        let lobby = irc_state
+            .rooms
+            .get(&room_id)
+            .and_then(|room| room.lobby_state.as_ref())
+            .ok_or("Lobby not found")?;
+        let mappool_id = lobby
+            .current_mappool_id
+            .ok_or("No mappool selected for this lobby")?;
+        let sender = irc_state
+            .message_sender
+            .clone()
+            .ok_or("IRC client not initialized")?;
+        (mappool_id, sender)
+    };
+
+    let pool = sqlite_pool(&app_handle).await?;
+    let entries = mappool::pool_beatmaps(&pool, mappool_id as i64)
+        .await
+        .map_err(|e| format!("Failed to load mappool: {}", e))?;
+    let entry = mappool::find_slot(&entries, &slot_code)
+        .ok_or_else(|| format!("No slot {} in this mappool", slot_code))?
+        .clone();
+    let beatmap_id = entry.beatmap_id as u64;
+    let no_fail = no_fail.unwrap_or(true);
+
+    let map_changes_before = {
+        let mut irc_state = state.lock().unwrap();
+        let lobby = irc_state
+            .rooms
+            .get_mut(&room_id)
+            .and_then(|room| room.lobby_state.as_mut())
+            .ok_or("Lobby not found")?;
+        lobby.selected_slot = Some(SelectedSlot::new(&slot_code, &entry, no_fail));
+        lobby.slot_mismatches.clear();
+        lobby.map_changes
+    };
+
+    let messages = [
+        format!("!mp map {} {}", beatmap_id, mode.unwrap_or(0)),
+        format!(
+            "!mp mods {}",
+            mappool::mods_argument(entry.mod_combination.as_deref(), no_fail)
+        ),
+    ];
+    for message in messages {
+        sender
+            .send(IrcCommand::SendMessage {
+                room_id: room_id.clone(),
+                message,
+                priority: SendPriority::Normal,
+            })
+            .map_err(|_| "Failed to queue message for sending".to_string())?;
+    }
+
+    // Wait for the "Changed beatmap to" reply that follows our `!mp map`, even when
+    // the lobby was already on that map
+    let deadline = tokio::time::Instant::now() + MAP_CHANGE_TIMEOUT;
+    loop {
+        let changed_to = state
+            .lock()
+            .unwrap()
+            .rooms
+            .get(&room_id)
+            .and_then(|room| room.lobby_state.as_ref())
+            .filter(|lobby| lobby.map_changes != map_changes_before)
+            .map(|lobby| lobby.current_map.as_ref().map(|map| map.beatmap_id));
+
+        match changed_to {
+            Some(Some(changed_to)) if changed_to == beatmap_id => return Ok(entry),
+            Some(Some(other)) => {
+                return Err(format!(
+                    "BanchoBot changed the map to {} instead of {}",
+                    other, beatmap_id
+                ))
+            }
+            _ => {}
+        }
+        if tokio::time::Instant::now() > deadline {
+            return Err("BanchoBot did not confirm the map change".to_string());
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+}
+
+/// Plays a whole mappool in the lobby, map by map, see `QualifierRun`.
+#[tauri::command]
+pub async fn start_qualifier(
+    room_id: String,
+    mappool_id: u64,
+    mode: Option<u8>,
+    ready_timer: u32,
+    start_delay: u32,
+    state: State<'_, IrcState>,
+    app_handle: tauri::AppHandle,
+) -> Result<QualifierRun, String> {
+    if ready_timer == 0 || ready_timer > 300 || start_delay > 300 {
+        return Err("Timers must be between 1 and 300 seconds".to_string());
+    }
+
+    let pool = sqlite_pool(&app_handle).await?;
+    let entries = mappool::pool_beatmaps(&pool, mappool_id as i64)
+        .await
+        .map_err(|e| format!("Failed to load mappool: {}", e))?;
+    let run = QualifierRun::new(
+        mappool_id,
+        mode.unwrap_or(0),
+        &entries,
+        ready_timer,
+        start_delay,
+    )?;
+
+    update_qualifier(&room_id, &state, |lobby| {
+        if lobby.match_status == "active" {
+            return Err("Wait for the current map to finish first".to_string());
+        }
+        let messages = run.setup_commands();
+        lobby.current_mappool_id = Some(mappool_id);
+        lobby.qualifier = Some(run);
+        Ok(messages)
+    })
+}
+
+#[tauri::command]
+pub async fn pause_qualifier(
+    room_id: String,
+    state: State<'_, IrcState>,
+) -> Result<QualifierRun, String> {
+    update_qualifier(&room_id, &state, |lobby| Ok(qualifier_mut(lobby)?.pause()))
+}
+
+#[tauri::command]
+pub async fn resume_qualifier(
+    room_id: String,
+    state: State<'_, IrcState>,
+) -> Result<QualifierRun, String> {
+    update_qualifier(&room_id, &state, |lobby| Ok(qualifier_mut(lobby)?.resume()))
+}
+
+#[tauri::command]
+pub async fn skip_qualifier_map(
+    room_id: String,
+    state: State<'_, IrcState>,
+) -> Result<QualifierRun, String> {
+    update_qualifier(&room_id, &state, |lobby| qualifier_mut(lobby)?.skip())
+}
+
+#[tauri::command]
+pub async fn replay_qualifier_map(
+    room_id: String,
+    map_index: usize,
+    state: State<'_, IrcState>,
+) -> Result<QualifierRun, String> {
+    update_qualifier(&room_id, &state, |lobby| {
+        qualifier_mut(lobby)?.replay(map_index)
+    })
+}
+
+/// Stops a qualifier run and returns it with the results collected so far.
+#[tauri::command]
+pub async fn stop_qualifier(
+    room_id: String,
+    state: State<'_, IrcState>,
+) -> Result<QualifierRun, String> {
+    let mut irc_state = state.lock().unwrap();
+    let lobby = irc_state
+        .rooms
+        .get_mut(&room_id)
+        .and_then(|room| room.lobby_state.as_mut())
+        .ok_or("Lobby not found")?;
+    lobby
+        .qualifier
+        .take()
+        .ok_or_else(|| "No qualifier run in this lobby".to_string())
+}
+
+fn qualifier_mut(lobby: &mut LobbyState) -> Result<&mut QualifierRun, String> {
+    lobby
+        .qualifier
+        .as_mut()
+        .ok_or_else(|| "No qualifier run in this lobby".to_string())
+}
+
+/// Changes a lobby's qualifier run and sends the messages the change calls for.
+fn update_qualifier<F>(room_id: &str, state: &IrcState, update: F) -> Result<QualifierRun, String>
+where
+    F: FnOnce(&mut LobbyState) -> Result<Vec<String>, String>,
+{
+    let mut irc_state = state.lock().unwrap();
+    let lobby = irc_state
+        .rooms
+        .get_mut(room_id)
+        .and_then(|room| room.lobby_state.as_mut())
+        .ok_or("Lobby not found")?;
+    let messages = update(lobby)?;
+    let run = lobby
+        .qualifier
+        .clone()
+        .ok_or("No qualifier run in this lobby")?;
+
+    if let Some(sender) = &irc_state.message_sender {
+        for message in messages {
+            let _ = sender.send(IrcCommand::SendMessage {
+                room_id: room_id.to_string(),
+                priority: SendPriority::of(&message),
+                message,
+            });
+        }
+    }
+
+    Ok(run)
+}
+
+/// Changes a lobby's draft and saves the result, so it survives a restart mid-match.
+async fn update_draft<F>(
+    room_id: &str,
+    state: &IrcState,
+    app_handle: &tauri::AppHandle,
+    update: F,
+) -> Result<MatchDraft, String>
+where
+    F: FnOnce(&mut LobbyState) -> Result<(), String>,
+{
+    let saved = {
+        let mut irc_state = state.lock().unwrap();
+        let lobby = irc_state
+            .rooms
+            .get_mut(room_id)
+            .and_then(|room| room.lobby_state.as_mut())
+            .ok_or("Lobby not found")?;
+        update(lobby)?;
+        lobby
+            .saved_draft()
+            .ok_or("No draft configured for this lobby")?
+    };
+
+    let pool = sqlite_pool(app_handle).await?;
+    save_draft(&pool, room_id, Some(&saved))
+        .await
+        .map_err(|e| format!("Failed to save draft: {}", e))?;
+
+    Ok(saved.draft)
+}
+
+/// Saves a changed scoreline with the lobby's draft, whose turns depend on it.
+/// Lobbies without a draft have nothing to save.
+async fn save_scoreline(
+    room_id: &str,
+    saved: Option<SavedDraft>,
+    app_handle: &tauri::AppHandle,
+) -> Result<(), String> {
+    let Some(saved) = saved else {
+        return Ok(());
+    };
+    let pool = sqlite_pool(app_handle).await?;
+    save_draft(&pool, room_id, Some(&saved))
+        .await
+        .map_err(|e| format!("Failed to save draft: {}", e))
+}
+
+/// Beatmap metadata, from the cache while it is fresh. `force_refresh` always asks
+/// osu!, and a stale entry is still used when osu! can't be reached.
 #[tauri::command]
 pub async fn fetch_beatmap_data(
     beatmap_id: String,
-    access_token: String,
+    force_refresh: Option<bool>,
+    api: State<'_, OsuApiClient>,
+    cache: State<'_, BeatmapCache>,
+    app_handle: tauri::AppHandle,
 ) -> Result<BeatmapData, String> {
-    let client = reqwest::Client::new();
-    let response = client
-        .get(&format!(
-            "https://osu.ppy.sh/api/v2/beatmaps/{}",
-            beatmap_id
-        ))
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Type", "application/json")
-        .send()
+    let beatmap_id: u64 = beatmap_id
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid beatmap id: {}", beatmap_id))?;
+    let pool = sqlite_pool(&app_handle).await?;
+
+    let cached = match cache.load(&pool, beatmap_id).await {
+        Ok(cached) => cached,
+        Err(e) => {
+            println!("Failed to read beatmap cache: {}", e);
+            None
+        }
+    };
+    if let Some(cached) = &cached {
+        if !cached.stale && !force_refresh.unwrap_or(false) {
+            return Ok(cached.data.clone());
+        }
+    }
+
+    match download_beatmap(&api, beatmap_id).await {
+        Ok(beatmap) => {
+            if let Err(e) = cache.store(&pool, &beatmap).await {
+                println!("Failed to cache beatmap {}: {}", beatmap_id, e);
+            }
+            Ok(beatmap)
+        }
+        Err(BeatmapFetchError::NotFound) => Err("Beatmap not found".to_string()),
+        Err(BeatmapFetchError::Failed(e)) => match cached {
+            Some(cached) => {
+                println!("Using cached beatmap {}: {}", beatmap_id, e);
+                Ok(cached.data)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+enum BeatmapFetchError {
+    NotFound,
+    Failed(String),
+}
+
+async fn download_beatmap(
+    api: &OsuApiClient,
+    beatmap_id: u64,
+) -> Result<BeatmapData, BeatmapFetchError> {
+    let response = api
+        .get(&format!("/beatmaps/{}", beatmap_id))
         .await
-        .map_err(|e| format!("Failed to fetch beatmap data: {}", e))?;
+        .map_err(|e| BeatmapFetchError::Failed(format!("Failed to fetch beatmap data: {}", e)))?;
 
     if !response.status().is_success() {
         if response.status().as_u16() == 404 {
-            return Err("Beatmap not found".to_string());
+            return Err(BeatmapFetchError::NotFound);
         }
+        return Err(BeatmapFetchError::Failed(format!(
+            "Failed to fetch beatmap data: {}",
+            response.status()
+        )));
+    }
+
+    let api_response: OsuApiBeatmapResponse = response
+        .json()
+        .await
+        .map_err(|e| BeatmapFetchError::Failed(format!("Failed to parse API response: {}", e)))?;
+
+    Ok(BeatmapData::from(api_response))
+}
+
+/// Looks up many beatmaps at once, answered in the order of `beatmap_ids`. Cached maps
+/// are used like in `fetch_beatmap_data`, the rest is asked for in batches of up to
+/// 50 ids. Every id gets its data or its own error.
+#[tauri::command]
+pub async fn fetch_beatmaps_batch(
+    beatmap_ids: Vec<u64>,
+    force_refresh: Option<bool>,
+    api: State<'_, OsuApiClient>,
+    cache: State<'_, BeatmapCache>,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<BeatmapLookup>, String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    let force_refresh = force_refresh.unwrap_or(false);
+
+    let mut cached = HashMap::new();
+    for &beatmap_id in &beatmap_ids {
+        if cached.contains_key(&beatmap_id) {
+            continue;
+        }
+        match cache.load(&pool, beatmap_id).await {
+            Ok(Some(entry)) => {
+                cached.insert(beatmap_id, entry);
+            }
+            Ok(None) => {}
+            Err(e) => println!("Failed to read beatmap cache: {}", e),
+        }
+    }
+
+    let mut downloads = Vec::new();
+    for chunk in download_chunks(&beatmap_ids, &cached, force_refresh) {
+        let response = download_beatmaps(&api, &chunk).await;
+        for beatmap in response.iter().flatten() {
+            if let Err(e) = cache.store(&pool, beatmap).await {
+                println!("Failed to cache beatmap {}: {}", beatmap.id, e);
+            }
+        }
+        downloads.push((chunk, response));
+    }
+
+    Ok(merge_lookups(&beatmap_ids, &cached, downloads))
+}
+
+async fn download_beatmaps(
+    api: &OsuApiClient,
+    beatmap_ids: &[u64],
+) -> Result<Vec<BeatmapData>, String> {
+    let query = beatmap_ids
+        .iter()
+        .map(|id| format!("ids%5B%5D={}", id))
+        .collect::<Vec<_>>()
+        .join("&");
+    let response = api
+        .get(&format!("/beatmaps?{}", query))
+        .await
+        .map_err(|e| format!("Failed to fetch beatmap data: {}", e))?;
+
+    if !response.status().is_success() {
         return Err(format!(
             "Failed to fetch beatmap data: {}",
             response.status()
         ));
     }
 
-    let api_response: OsuApiBeatmapResponse = response
+    let api_response: OsuApiBeatmapsResponse = response
         .json()
         .await
         .map_err(|e| format!("Failed to parse API response: {}", e))?;
 
-    Ok(BeatmapData {
-        id: api_response.id,
-        beatmapset_id: api_response.beatmapset_id,
-        artist: api_response.beatmapset.artist,
-        title: api_response.beatmapset.title,
-        difficulty: api_response.version,
-        mapper: api_response.beatmapset.creator,
-        mode: api_response.mode_int,
-        total_length: api_response.total_length,
-        bpm: api_response.bpm,
-        difficulty_rating: api_response.difficulty_rating,
-    })
+    Ok(api_response
+        .beatmaps
+        .into_iter()
+        .map(BeatmapData::from)
+        .collect())
+}
+
+/// How many hours beatmap metadata stays cached before osu! is asked again.
+#[tauri::command]
+pub async fn get_beatmap_cache_ttl(cache: State<'_, BeatmapCache>) -> Result<u32, String> {
+    Ok(cache.ttl().num_hours() as u32)
+}
+
+#[tauri::command]
+pub async fn set_beatmap_cache_ttl(
+    hours: u32,
+    cache: State<'_, BeatmapCache>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    if hours == 0 {
+        return Err("Cache lifetime must be at least an hour".to_string());
+    }
+    let pool = sqlite_pool(&app_handle).await?;
+    cache
+        .save_ttl(&pool, hours)
+        .await
+        .map_err(|e| format!("Failed to save cache lifetime: {}", e))
 }
 
 #[tauri::command]
-pub async fn fetch_user_data(username: String, access_token: String) -> Result<UserData, String> {
-    let client = reqwest::Client::new();
-    let response = client
-        .get(&format!("https://osu.ppy.sh/api/v2/users/@{}", username))
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Type", "application/json")
-        .send()
+pub async fn fetch_user_data(
+    username: String,
+    api: State<'_, OsuApiClient>,
+) -> Result<UserData, String> {
+    let response = api
+        .get(&format!("/users/@{}", username))
         .await
         .map_err(|e| format!("Failed to fetch user data: {}", e))?;
 
@@ -467,6 +1447,81 @@ pub async fn fetch_user_data(username: String, access_token: String) -> Result<U
     })
 }
 
+#[tauri::command]
+pub async fn save_credentials(
+    username: String,
+    password: String,
+    secrets: State<'_, SecretStore>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    credentials::save_credentials(&pool, &secrets, &username, &password).await
+}
+
+#[tauri::command]
+pub async fn get_credentials(
+    secrets: State<'_, SecretStore>,
+    app_handle: tauri::AppHandle,
+) -> Result<Option<UserCredentials>, String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    credentials::load_credentials(&pool, &secrets).await
+}
+
+#[tauri::command]
+pub async fn delete_credentials(
+    secrets: State<'_, SecretStore>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    credentials::delete_credentials(&pool, &secrets).await
+}
+
+#[tauri::command]
+pub async fn save_oauth_token(
+    username: String,
+    access_token: String,
+    refresh_token: String,
+    expires_in: i64,
+    secrets: State<'_, SecretStore>,
+    api: State<'_, OsuApiClient>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let pool = sqlite_pool(&app_handle).await?;
+    let token = credentials::save_oauth_token(
+        &pool,
+        &secrets,
+        &username,
+        &access_token,
+        &refresh_token,
+        expires_in,
+    )
+    .await?;
+    api.set_token(token).await;
+    Ok(())
+}
+
+/// Hands the stored osu! tokens of an IRC account to the API client. Returns whether
+/// an osu! account is connected.
+#[tauri::command]
+pub async fn connect_osu_account(
+    username: String,
+    api: State<'_, OsuApiClient>,
+) -> Result<bool, String> {
+    api.connect(&username).await
+}
+
+#[tauri::command]
+pub async fn delete_oauth_token(
+    username: String,
+    secrets: State<'_, SecretStore>,
+    api: State<'_, OsuApiClient>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    api.disconnect().await;
+    let pool = sqlite_pool(&app_handle).await?;
+    credentials::delete_oauth_token(&pool, &secrets, &username).await
+}
+
 pub fn remove_room(room_id: &str, state: &IrcState) {
     let mut irc_state = state.lock().unwrap();
     irc_state.rooms.remove(room_id);
@@ -485,6 +1540,24 @@ pub fn clear_lobby_state(room_id: &str, state: &IrcState) {
                 slot.player = None;
             }
             lobby.match_status = "idle".to_string();
+            lobby.selected_slot = None;
+            lobby.slot_mismatches.clear();
+        }
+    }
+}
+
+/// Empties the slots before a rejoin's `!mp settings` fills them again. Unlike
+/// `clear_lobby_state` the selected slot and match status are kept, since the lobby
+/// may still be on that map or even playing it.
+pub fn clear_lobby_slots(room_id: &str, state: &IrcState) {
+    let mut irc_state = state.lock().unwrap();
+    if let Some(lobby) = irc_state
+        .rooms
+        .get_mut(room_id)
+        .and_then(|room| room.lobby_state.as_mut())
+    {
+        for slot in &mut lobby.slots {
+            slot.player = None;
         }
     }
 }
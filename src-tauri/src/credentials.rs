@@ -0,0 +1,276 @@
+use crate::secrets::SecretStore;
+use chrono::{SecondsFormat, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+// The `user_credentials` and `oauth_tokens` rows only keep metadata now, the password
+// and tokens columns stay empty and the values live in the secret store. The structs
+// keep the row shapes the frontend used to read itself.
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserCredentials {
+    pub id: i64,
+    pub username: String,
+    pub password: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OAuthToken {
+    pub username: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+    pub expires_at: String,
+}
+
+/// What goes into the secret store for an osu! account.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredTokens {
+    access_token: String,
+    refresh_token: String,
+}
+
+type CredentialsRow = (i64, String, String, String);
+
+fn password_secret(username: &str) -> String {
+    format!("irc-password/{}", username)
+}
+
+fn oauth_secret(username: &str) -> String {
+    format!("oauth/{}", username)
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn database_error(e: sqlx::Error) -> String {
+    format!("Database error: {}", e)
+}
+
+pub async fn save_credentials(
+    pool: &SqlitePool,
+    secrets: &SecretStore,
+    username: &str,
+    password: &str,
+) -> Result<(), String> {
+    secrets.set(&password_secret(username), password).await?;
+
+    let now = now();
+    sqlx::query(
+        "INSERT INTO user_credentials (username, password, created_at, updated_at)
+         VALUES (?, '', ?, ?)
+         ON CONFLICT(username) DO UPDATE SET
+           password = '',
+           updated_at = excluded.updated_at",
+    )
+    .bind(username)
+    .bind(&now)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .map_err(database_error)?;
+    Ok(())
+}
+
+/// The remembered IRC login, if there is one and its password is still in the store.
+pub async fn load_credentials(
+    pool: &SqlitePool,
+    secrets: &SecretStore,
+) -> Result<Option<UserCredentials>, String> {
+    let row: Option<CredentialsRow> =
+        sqlx::query_as("SELECT id, username, created_at, updated_at FROM user_credentials LIMIT 1")
+            .fetch_optional(pool)
+            .await
+            .map_err(database_error)?;
+
+    let Some((id, username, created_at, updated_at)) = row else {
+        return Ok(None);
+    };
+    let Some(password) = secrets.get(&password_secret(&username)).await? else {
+        println!("Password of {} is missing from the secret store", username);
+        return Ok(None);
+    };
+
+    Ok(Some(UserCredentials {
+        id,
+        username,
+        password,
+        created_at,
+        updated_at,
+    }))
+}
+
+pub async fn delete_credentials(pool: &SqlitePool, secrets: &SecretStore) -> Result<(), String> {
+    let usernames: Vec<(String,)> = sqlx::query_as("SELECT username FROM user_credentials")
+        .fetch_all(pool)
+        .await
+        .map_err(database_error)?;
+    for (username,) in usernames {
+        secrets.delete(&password_secret(&username)).await?;
+    }
+
+    sqlx::query("DELETE FROM user_credentials")
+        .execute(pool)
+        .await
+        .map_err(database_error)?;
+    Ok(())
+}
+
+pub async fn save_oauth_token(
+    pool: &SqlitePool,
+    secrets: &SecretStore,
+    username: &str,
+    access_token: &str,
+    refresh_token: &str,
+    expires_in: i64,
+) -> Result<OAuthToken, String> {
+    let tokens = StoredTokens {
+        access_token: access_token.to_string(),
+        refresh_token: refresh_token.to_string(),
+    };
+    let json = serde_json::to_string(&tokens).map_err(|e| e.to_string())?;
+    secrets.set(&oauth_secret(username), &json).await?;
+
+    let now = Utc::now();
+    let expires_at =
+        (now + TimeDelta::seconds(expires_in)).to_rfc3339_opts(SecondsFormat::Millis, true);
+    let now = now.to_rfc3339_opts(SecondsFormat::Millis, true);
+    sqlx::query(
+        "INSERT INTO oauth_tokens
+         (irc_username, access_token, refresh_token, expires_in, expires_at, created_at, updated_at)
+         VALUES (?, '', '', ?, ?, ?, ?)
+         ON CONFLICT(irc_username) DO UPDATE SET
+           access_token = '',
+           refresh_token = '',
+           expires_in = excluded.expires_in,
+           expires_at = excluded.expires_at,
+           updated_at = excluded.updated_at",
+    )
+    .bind(username)
+    .bind(expires_in)
+    .bind(&expires_at)
+    .bind(&now)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .map_err(database_error)?;
+
+    Ok(OAuthToken {
+        username: username.to_string(),
+        access_token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        expires_in,
+        expires_at,
+    })
+}
+
+pub async fn load_oauth_token(
+    pool: &SqlitePool,
+    secrets: &SecretStore,
+    username: &str,
+) -> Result<Option<OAuthToken>, String> {
+    let row: Option<(i64, String)> = sqlx::query_as(
+        "SELECT expires_in, expires_at FROM oauth_tokens WHERE irc_username = ? LIMIT 1",
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await
+    .map_err(database_error)?;
+
+    let Some((expires_in, expires_at)) = row else {
+        return Ok(None);
+    };
+    let Some(json) = secrets.get(&oauth_secret(username)).await? else {
+        println!(
+            "osu! tokens of {} are missing from the secret store",
+            username
+        );
+        return Ok(None);
+    };
+    let tokens: StoredTokens =
+        serde_json::from_str(&json).map_err(|e| format!("Stored tokens are invalid: {}", e))?;
+
+    Ok(Some(OAuthToken {
+        username: username.to_string(),
+        access_token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        expires_in,
+        expires_at,
+    }))
+}
+
+pub async fn delete_oauth_token(
+    pool: &SqlitePool,
+    secrets: &SecretStore,
+    username: &str,
+) -> Result<(), String> {
+    secrets.delete(&oauth_secret(username)).await?;
+    sqlx::query("DELETE FROM oauth_tokens WHERE irc_username = ?")
+        .bind(username)
+        .execute(pool)
+        .await
+        .map_err(database_error)?;
+    Ok(())
+}
+
+/// Moves passwords and tokens saved by older versions from the database into the
+/// secret store. A column is only emptied once its value is stored, so a failure
+/// leaves it for the next start. Returns how many rows were moved.
+pub async fn move_plaintext_secrets(
+    pool: &SqlitePool,
+    secrets: &SecretStore,
+) -> Result<usize, String> {
+    let mut moved = 0;
+
+    let passwords: Vec<(String, String)> =
+        sqlx::query_as("SELECT username, password FROM user_credentials WHERE password != ''")
+            .fetch_all(pool)
+            .await
+            .map_err(database_error)?;
+    for (username, password) in passwords {
+        secrets.set(&password_secret(&username), &password).await?;
+        sqlx::query("UPDATE user_credentials SET password = '' WHERE username = ?")
+            .bind(&username)
+            .execute(pool)
+            .await
+            .map_err(database_error)?;
+        moved += 1;
+    }
+
+    let tokens: Vec<(String, String, String)> = sqlx::query_as(
+        "SELECT irc_username, access_token, refresh_token FROM oauth_tokens
+         WHERE access_token != '' OR refresh_token != ''",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(database_error)?;
+    for (username, access_token, refresh_token) in tokens {
+        let json = serde_json::to_string(&StoredTokens {
+            access_token,
+            refresh_token,
+        })
+        .map_err(|e| e.to_string())?;
+        secrets.set(&oauth_secret(&username), &json).await?;
+        sqlx::query(
+            "UPDATE oauth_tokens SET access_token = '', refresh_token = '' WHERE irc_username = ?",
+        )
+        .bind(&username)
+        .execute(pool)
+        .await
+        .map_err(database_error)?;
+        moved += 1;
+    }
+
+    if moved > 0 {
+        // Emptied values would otherwise linger in free pages of the file
+        if let Err(e) = sqlx::query("VACUUM").execute(pool).await {
+            println!("Failed to vacuum the database: {}", e);
+        }
+    }
+
+    Ok(moved)
+}
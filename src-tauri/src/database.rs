@@ -0,0 +1,20 @@
+use sqlx::SqlitePool;
+use tauri::{Manager, Runtime};
+use tauri_plugin_sql::{DbInstances, DbPool};
+
+pub const DATABASE_URL: &str = "sqlite:osu_reffer_database.db";
+
+/// Returns the SQLite pool opened (and migrated) by the SQL plugin at startup.
+pub async fn sqlite_pool<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+) -> Result<SqlitePool, String> {
+    let instances = app_handle
+        .try_state::<DbInstances>()
+        .ok_or_else(|| "Database plugin is not initialized".to_string())?;
+
+    let instances = instances.0.read().await;
+    match instances.get(DATABASE_URL) {
+        Some(DbPool::Sqlite(pool)) => Ok(pool.clone()),
+        None => Err("Database is not loaded".to_string()),
+    }
+}
@@ -0,0 +1,439 @@
+use crate::types::MatchScoreline;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DraftActionKind {
+    Protect,
+    Ban,
+    Pick,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DraftConfig {
+    pub red_captain: Option<String>,
+    pub blue_captain: Option<String>,
+    pub first_pick: String, // "red" or "blue"
+    pub first_ban: String,  // "red" or "blue", also protects first
+    pub protects_per_team: u8,
+    pub bans_per_team: u8,
+    pub tiebreaker_beatmap_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DraftAction {
+    pub kind: DraftActionKind,
+    pub team: String,
+    pub beatmap_id: u64,
+    pub timestamp: u64,
+}
+
+/// Whose turn it is in the draft.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum DraftTurn {
+    Action { kind: DraftActionKind, team: String },
+    Tiebreaker,
+    Finished,
+}
+
+/// The pick/ban draft of a match: protects, then bans, then alternating picks
+/// until the scoreline calls for the tiebreaker.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchDraft {
+    pub config: DraftConfig,
+    pub actions: Vec<DraftAction>,
+    /// The most recent pick, i.e. the map that should be played next.
+    pub current_pick: Option<DraftAction>,
+}
+
+/// What is saved for a lobby: its draft and the scoreline the draft's turns depend on.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedDraft {
+    #[serde(flatten)]
+    pub draft: MatchDraft,
+    /// Drafts saved before the scoreline was kept with them start from an empty one.
+    #[serde(default)]
+    pub scoreline: MatchScoreline,
+}
+
+impl MatchDraft {
+    pub fn new(config: DraftConfig) -> Result<Self, String> {
+        validate_team(&config.first_pick)?;
+        validate_team(&config.first_ban)?;
+
+        Ok(Self {
+            config,
+            actions: Vec::new(),
+            current_pick: None,
+        })
+    }
+
+    pub fn next_turn(&self, scoreline: &MatchScoreline) -> DraftTurn {
+        if scoreline.match_winner.is_some() {
+            return DraftTurn::Finished;
+        }
+
+        let protects = self.count(DraftActionKind::Protect);
+        if protects < self.config.protects_per_team as usize * 2 {
+            return DraftTurn::Action {
+                kind: DraftActionKind::Protect,
+                team: alternate(&self.config.first_ban, protects),
+            };
+        }
+
+        let bans = self.count(DraftActionKind::Ban);
+        if bans < self.config.bans_per_team as usize * 2 {
+            return DraftTurn::Action {
+                kind: DraftActionKind::Ban,
+                team: alternate(&self.config.first_ban, bans),
+            };
+        }
+
+        if is_tiebreaker(scoreline) {
+            return DraftTurn::Tiebreaker;
+        }
+
+        DraftTurn::Action {
+            kind: DraftActionKind::Pick,
+            team: alternate(&self.config.first_pick, self.count(DraftActionKind::Pick)),
+        }
+    }
+
+    /// Records a protect, ban or pick after checking that it's that team's turn and
+    /// that the beatmap is still available.
+    pub fn record(
+        &mut self,
+        kind: DraftActionKind,
+        team: &str,
+        beatmap_id: u64,
+        timestamp: u64,
+        scoreline: &MatchScoreline,
+    ) -> Result<(), String> {
+        validate_team(team)?;
+
+        match self.next_turn(scoreline) {
+            DraftTurn::Action {
+                kind: expected_kind,
+                team: expected_team,
+            } => {
+                if expected_kind != kind || expected_team != team {
+                    return Err(format!(
+                        "It is {}'s turn to {}",
+                        expected_team,
+                        kind_name(expected_kind)
+                    ));
+                }
+            }
+            DraftTurn::Tiebreaker => {
+                return Err("The tiebreaker is next, no more picks or bans".to_string())
+            }
+            DraftTurn::Finished => return Err("The match is already decided".to_string()),
+        }
+
+        if self.config.tiebreaker_beatmap_id == Some(beatmap_id) {
+            return Err("The tiebreaker cannot be protected, banned or picked".to_string());
+        }
+        if self.has(DraftActionKind::Ban, beatmap_id) {
+            return Err("This beatmap is banned".to_string());
+        }
+        if self.has(DraftActionKind::Pick, beatmap_id) {
+            return Err("This beatmap was already picked".to_string());
+        }
+        if kind != DraftActionKind::Pick && self.has(DraftActionKind::Protect, beatmap_id) {
+            return Err("This beatmap is protected".to_string());
+        }
+
+        let action = DraftAction {
+            kind,
+            team: team.to_string(),
+            beatmap_id,
+            timestamp,
+        };
+        if kind == DraftActionKind::Pick {
+            self.current_pick = Some(action.clone());
+        }
+        self.actions.push(action);
+
+        Ok(())
+    }
+
+    /// Removes the last recorded action, for when a referee misclicks.
+    pub fn undo(&mut self) -> Option<DraftAction> {
+        let action = self.actions.pop()?;
+        if action.kind == DraftActionKind::Pick {
+            self.current_pick = self
+                .actions
+                .iter()
+                .rev()
+                .find(|a| a.kind == DraftActionKind::Pick)
+                .cloned();
+        }
+        Some(action)
+    }
+
+    /// The team that picked the given beatmap, if it was picked.
+    pub fn picked_by(&self, beatmap_id: u64) -> Option<&str> {
+        self.actions
+            .iter()
+            .find(|a| a.kind == DraftActionKind::Pick && a.beatmap_id == beatmap_id)
+            .map(|a| a.team.as_str())
+    }
+
+    fn count(&self, kind: DraftActionKind) -> usize {
+        self.actions.iter().filter(|a| a.kind == kind).count()
+    }
+
+    fn has(&self, kind: DraftActionKind, beatmap_id: u64) -> bool {
+        self.actions
+            .iter()
+            .any(|a| a.kind == kind && a.beatmap_id == beatmap_id)
+    }
+}
+
+fn validate_team(team: &str) -> Result<(), String> {
+    match team {
+        "red" | "blue" => Ok(()),
+        other => Err(format!("Invalid team: {}", other)),
+    }
+}
+
+fn other_team(team: &str) -> &'static str {
+    if team == "red" {
+        "blue"
+    } else {
+        "red"
+    }
+}
+
+/// The team acting after `done` actions of a phase that `first` started.
+fn alternate(first: &str, done: usize) -> String {
+    if done.is_multiple_of(2) {
+        first.to_string()
+    } else {
+        other_team(first).to_string()
+    }
+}
+
+fn kind_name(kind: DraftActionKind) -> &'static str {
+    match kind {
+        DraftActionKind::Protect => "protect",
+        DraftActionKind::Ban => "ban",
+        DraftActionKind::Pick => "pick",
+    }
+}
+
+/// Both teams are one win away from taking a best-of match.
+fn is_tiebreaker(scoreline: &MatchScoreline) -> bool {
+    match scoreline.best_of {
+        Some(best_of) => {
+            let wins_needed = best_of / 2 + 1;
+            scoreline.red_wins == wins_needed - 1 && scoreline.blue_wins == wins_needed - 1
+        }
+        None => false,
+    }
+}
+
+pub async fn load_draft(
+    pool: &SqlitePool,
+    room_id: &str,
+) -> Result<Option<SavedDraft>, sqlx::Error> {
+    let row: Option<(String,)> = sqlx::query_as("SELECT draft FROM match_drafts WHERE room_id = ?")
+        .bind(room_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.and_then(|(draft,)| match serde_json::from_str(&draft) {
+        Ok(draft) => Some(draft),
+        Err(e) => {
+            println!("Ignoring unreadable draft for {}: {}", room_id, e);
+            None
+        }
+    }))
+}
+
+/// Stores the draft of a room, or deletes it when `saved` is `None`.
+pub async fn save_draft(
+    pool: &SqlitePool,
+    room_id: &str,
+    saved: Option<&SavedDraft>,
+) -> Result<(), sqlx::Error> {
+    match saved {
+        Some(saved) => {
+            let json = serde_json::to_string(saved).unwrap_or_default();
+            sqlx::query(
+                "INSERT INTO match_drafts (room_id, draft, updated_at)
+                 VALUES (?, ?, datetime('now'))
+                 ON CONFLICT(room_id) DO UPDATE SET draft = excluded.draft, updated_at = excluded.updated_at",
+            )
+            .bind(room_id)
+            .bind(json)
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM match_drafts WHERE room_id = ?")
+                .bind(room_id)
+                .execute(pool)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draft(protects: u8, bans: u8) -> MatchDraft {
+        MatchDraft::new(DraftConfig {
+            red_captain: Some("RedCaptain".to_string()),
+            blue_captain: Some("BlueCaptain".to_string()),
+            first_pick: "red".to_string(),
+            first_ban: "blue".to_string(),
+            protects_per_team: protects,
+            bans_per_team: bans,
+            tiebreaker_beatmap_id: Some(99),
+        })
+        .unwrap()
+    }
+
+    fn turn(kind: DraftActionKind, team: &str) -> DraftTurn {
+        DraftTurn::Action {
+            kind,
+            team: team.to_string(),
+        }
+    }
+
+    #[test]
+    fn follows_protect_ban_pick_order() {
+        let mut draft = draft(1, 1);
+        let scoreline = MatchScoreline::default();
+
+        assert_eq!(
+            draft.next_turn(&scoreline),
+            turn(DraftActionKind::Protect, "blue")
+        );
+        draft
+            .record(DraftActionKind::Protect, "blue", 1, 0, &scoreline)
+            .unwrap();
+        draft
+            .record(DraftActionKind::Protect, "red", 2, 0, &scoreline)
+            .unwrap();
+        draft
+            .record(DraftActionKind::Ban, "blue", 3, 0, &scoreline)
+            .unwrap();
+        draft
+            .record(DraftActionKind::Ban, "red", 4, 0, &scoreline)
+            .unwrap();
+
+        assert_eq!(
+            draft.next_turn(&scoreline),
+            turn(DraftActionKind::Pick, "red")
+        );
+        draft
+            .record(DraftActionKind::Pick, "red", 1, 0, &scoreline)
+            .unwrap();
+        assert_eq!(draft.picked_by(1), Some("red"));
+        assert_eq!(draft.current_pick.as_ref().unwrap().beatmap_id, 1);
+        assert_eq!(
+            draft.next_turn(&scoreline),
+            turn(DraftActionKind::Pick, "blue")
+        );
+    }
+
+    #[test]
+    fn refuses_unavailable_beatmaps() {
+        let mut draft = draft(1, 1);
+        let scoreline = MatchScoreline::default();
+
+        draft
+            .record(DraftActionKind::Protect, "blue", 1, 0, &scoreline)
+            .unwrap();
+        assert!(draft
+            .record(DraftActionKind::Protect, "blue", 2, 0, &scoreline)
+            .is_err());
+        draft
+            .record(DraftActionKind::Protect, "red", 2, 0, &scoreline)
+            .unwrap();
+        assert!(draft
+            .record(DraftActionKind::Ban, "blue", 1, 0, &scoreline)
+            .is_err());
+        assert!(draft
+            .record(DraftActionKind::Ban, "blue", 99, 0, &scoreline)
+            .is_err());
+        draft
+            .record(DraftActionKind::Ban, "blue", 3, 0, &scoreline)
+            .unwrap();
+        draft
+            .record(DraftActionKind::Ban, "red", 4, 0, &scoreline)
+            .unwrap();
+        assert!(draft
+            .record(DraftActionKind::Pick, "red", 3, 0, &scoreline)
+            .is_err());
+        draft
+            .record(DraftActionKind::Pick, "red", 5, 0, &scoreline)
+            .unwrap();
+        assert!(draft
+            .record(DraftActionKind::Pick, "blue", 5, 0, &scoreline)
+            .is_err());
+
+        assert_eq!(draft.undo().unwrap().beatmap_id, 5);
+        assert!(draft.current_pick.is_none());
+    }
+
+    #[test]
+    fn tiebreaker_ends_picks() {
+        let draft = draft(0, 0);
+        let scoreline = MatchScoreline {
+            red_wins: 3,
+            blue_wins: 3,
+            best_of: Some(7),
+            ..MatchScoreline::default()
+        };
+
+        assert_eq!(draft.next_turn(&scoreline), DraftTurn::Tiebreaker);
+    }
+
+    #[test]
+    fn saved_draft_resumes_at_the_tiebreaker() {
+        let saved = SavedDraft {
+            draft: draft(0, 0),
+            scoreline: MatchScoreline {
+                red_wins: 3,
+                blue_wins: 3,
+                best_of: Some(7),
+                ..MatchScoreline::default()
+            },
+        };
+
+        let json = serde_json::to_string(&saved).unwrap();
+        let restored: SavedDraft = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            restored.draft.next_turn(&restored.scoreline),
+            DraftTurn::Tiebreaker
+        );
+        assert!(restored
+            .draft
+            .clone()
+            .record(DraftActionKind::Pick, "red", 5, 0, &restored.scoreline)
+            .is_err());
+    }
+
+    #[test]
+    fn loads_drafts_saved_without_a_scoreline() {
+        let json = serde_json::to_string(&draft(1, 1)).unwrap();
+        let restored: SavedDraft = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.scoreline.best_of, None);
+        assert_eq!(
+            restored.draft.next_turn(&restored.scoreline),
+            turn(DraftActionKind::Protect, "blue")
+        );
+    }
+}
@@ -1,181 +1,461 @@
-use crate::banchobot_parser::BanchoBotParser;
+use crate::chat_log::ChatLog;
+use crate::commands::clear_lobby_slots;
+use crate::lobby_sync::{handle_lobby_message, handle_match_created, restore_draft};
+use crate::send_queue::{SendPriority, SendQueue};
 use crate::types::*;
 use futures::stream::StreamExt;
 use irc::client::prelude::*;
-use tauri::Emitter;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{Emitter, Manager, Runtime};
+
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+/// Why a single IRC connection stopped being handled.
+pub enum ConnectionEnd {
+    /// The user asked to disconnect (or nobody can send commands anymore).
+    Requested,
+    /// The connection to Bancho dropped on its own.
+    Lost,
+}
 
-pub async fn handle_irc_connection(
-    mut client: irc::client::Client,
-    app_handle: tauri::AppHandle,
+/// Creates a new client for the given config and identifies with Bancho.
+pub async fn connect_client(config: &ConnectionConfig) -> Result<irc::client::Client, String> {
+    let irc_config = Config {
+        nickname: Some(config.username.clone()),
+        server: Some(config.server().to_string()),
+        port: Some(config.port()),
+        username: Some(config.username.clone()),
+        password: Some(config.password.clone()),
+        use_tls: Some(config.use_tls()),
+        ..Config::default()
+    };
+
+    let client = irc::client::Client::from_config(irc_config)
+        .await
+        .map_err(|e| format!("Failed to connect: {}", e))?;
+
+    client
+        .identify()
+        .map_err(|e| format!("Failed to identify: {}", e))?;
+
+    Ok(client)
+}
+
+/// The channels and lobbies we were in, closed lobbies aside.
+fn rooms_to_rejoin(state: &IrcState) -> Vec<String> {
+    let irc_state = state.lock().unwrap();
+    irc_state
+        .rooms
+        .iter()
+        .filter_map(|(room_id, room)| match room.room_type {
+            RoomType::Channel | RoomType::MultiplayerLobby if !room.closed => Some(room_id.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Queues a JOIN for every channel and lobby we were in and asks BanchoBot for fresh
+/// `!mp settings` so each lobby state gets rebuilt. Returns the rejoined room ids.
+pub fn rejoin_rooms(state: &IrcState) -> Vec<String> {
+    let rooms_to_rejoin = rooms_to_rejoin(state);
+    let Some(sender) = state.lock().unwrap().message_sender.clone() else {
+        return Vec::new();
+    };
+
+    for room_id in &rooms_to_rejoin {
+        let _ = sender.send(IrcCommand::JoinChannel {
+            channel: room_id.clone(),
+        });
+
+        if room_id.starts_with("#mp_") {
+            clear_lobby_slots(room_id, state);
+            let _ = sender.send(IrcCommand::SendMessage {
+                room_id: room_id.clone(),
+                message: "!mp settings".to_string(),
+                priority: SendPriority::Normal,
+            });
+        }
+    }
+
+    rooms_to_rejoin
+}
+
+/// Like `rejoin_rooms`, but JOINs right away on a new connection. Messages queued while
+/// we were away are only sent once the connection is handled, after these JOINs, so
+/// they don't go to channels we're not in yet.
+fn rejoin_after_reconnect(
+    client: &irc::client::Client,
+    state: &IrcState,
+    send_queue: &mut SendQueue,
+) -> Vec<String> {
+    let rooms_to_rejoin = rooms_to_rejoin(state);
+
+    for room_id in &rooms_to_rejoin {
+        join_channel(client, room_id);
+
+        if room_id.starts_with("#mp_") {
+            clear_lobby_slots(room_id, state);
+            send_queue.push(IrcCommand::SendMessage {
+                room_id: room_id.clone(),
+                message: "!mp settings".to_string(),
+                priority: SendPriority::Normal,
+            });
+        }
+    }
+
+    rooms_to_rejoin
+}
+
+/// Runs the IRC connection and keeps it alive. When Bancho drops us the connection is
+/// re-established with exponential backoff and every room is rejoined and resynced.
+pub async fn supervise_irc_connection<R: Runtime>(
+    client: irc::client::Client,
+    config: ConnectionConfig,
+    app_handle: tauri::AppHandle<R>,
     state: IrcState,
     mut command_receiver: tokio::sync::mpsc::UnboundedReceiver<IrcCommand>,
 ) {
+    let mut client = client;
+    let mut send_queue = {
+        let irc_state = state.lock().unwrap();
+        SendQueue::new(&irc_state.rate_limit)
+    };
+
+    loop {
+        let end = handle_irc_connection(
+            client,
+            &app_handle,
+            &state,
+            &mut command_receiver,
+            &mut send_queue,
+        )
+        .await;
+        if let ConnectionEnd::Requested = end {
+            break;
+        }
+
+        {
+            let mut irc_state = state.lock().unwrap();
+            irc_state.connected = false;
+            irc_state.reconnecting = true;
+            irc_state.client = None;
+        }
+
+        let mut channel_changes = Vec::new();
+        match reconnect_with_backoff(
+            &config,
+            &app_handle,
+            &mut command_receiver,
+            &mut send_queue,
+            &mut channel_changes,
+        )
+        .await
+        {
+            Some(new_client) => {
+                client = new_client;
+
+                {
+                    let mut irc_state = state.lock().unwrap();
+                    irc_state.connected = true;
+                    irc_state.reconnecting = false;
+                }
+
+                let rejoined_rooms = rejoin_after_reconnect(&client, &state, &mut send_queue);
+                println!("Reconnected to Bancho, rejoining {:?}", rejoined_rooms);
+                for command in channel_changes {
+                    match command {
+                        IrcCommand::JoinChannel { channel } => join_channel(&client, &channel),
+                        IrcCommand::LeaveChannel { channel } => leave_channel(&client, &channel),
+                        _ => {}
+                    }
+                }
+
+                let _ = app_handle.emit(
+                    "irc-reconnected",
+                    serde_json::json!({ "rejoinedRooms": rejoined_rooms }),
+                );
+            }
+            None => break,
+        }
+    }
+
+    // Mark as disconnected
+    {
+        let mut irc_state = state.lock().unwrap();
+        irc_state.connected = false;
+        irc_state.reconnecting = false;
+        irc_state.client = None;
+        irc_state.message_sender = None;
+    }
+
+    if let Err(e) = app_handle.emit("irc-disconnected", ()) {
+        println!("Failed to emit disconnect event: {}", e);
+    }
+}
+
+/// Tries to connect again, doubling the delay after every failed attempt.
+/// Messages sent in the meantime stay queued until the connection is back, joins and
+/// parts are collected in `channel_changes` to be sent once the rooms are rejoined.
+/// Returns `None` when the user disconnects meanwhile or all attempts fail.
+async fn reconnect_with_backoff<R: Runtime>(
+    config: &ConnectionConfig,
+    app_handle: &tauri::AppHandle<R>,
+    command_receiver: &mut tokio::sync::mpsc::UnboundedReceiver<IrcCommand>,
+    send_queue: &mut SendQueue,
+    channel_changes: &mut Vec<IrcCommand>,
+) -> Option<irc::client::Client> {
+    let mut delay = RECONNECT_BASE_DELAY;
+
+    for attempt in 1..=MAX_RECONNECT_ATTEMPTS {
+        println!(
+            "Reconnecting to Bancho in {}s (attempt {}/{})",
+            delay.as_secs(),
+            attempt,
+            MAX_RECONNECT_ATTEMPTS
+        );
+
+        let _ = app_handle.emit(
+            "irc-reconnecting",
+            serde_json::json!({
+                "attempt": attempt,
+                "maxAttempts": MAX_RECONNECT_ATTEMPTS,
+                "delaySecs": delay.as_secs()
+            }),
+        );
+
+        // Wait out the backoff while still listening for a user disconnect
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => break,
+                command = command_receiver.recv() => match command {
+                    Some(IrcCommand::Disconnect) | None => return None,
+                    Some(command @ (IrcCommand::SendMessage { .. } | IrcCommand::SendPrivateMessage { .. })) => {
+                        send_queue.push(command);
+                    }
+                    Some(IrcCommand::SetRateLimit { config }) => send_queue.configure(&config),
+                    Some(command @ (IrcCommand::JoinChannel { .. } | IrcCommand::LeaveChannel { .. })) => {
+                        channel_changes.push(command);
+                    }
+                }
+            }
+        }
+
+        match connect_client(config).await {
+            Ok(client) => return Some(client),
+            Err(e) => println!("Reconnect attempt {} failed: {}", attempt, e),
+        }
+
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+
+    let _ = app_handle.emit("irc-reconnect-failed", ());
+    None
+}
+
+pub async fn handle_irc_connection<R: Runtime>(
+    mut client: irc::client::Client,
+    app_handle: &tauri::AppHandle<R>,
+    state: &IrcState,
+    command_receiver: &mut tokio::sync::mpsc::UnboundedReceiver<IrcCommand>,
+    send_queue: &mut SendQueue,
+) -> ConnectionEnd {
     println!("Starting IRC connection handler...");
 
     let mut stream = client.stream().unwrap();
 
-    loop {
+    // Send whatever was queued up while we were not connected
+    flush_send_queue(&client, send_queue, app_handle, state);
+
+    let end = loop {
+        let queue_delay = send_queue.time_until_ready();
+
         tokio::select! {
             message = stream.next() => {
                 match message {
                     Some(Ok(msg)) => {
-                        handle_incoming_message(msg, &app_handle, &state);
+                        handle_incoming_message(msg, app_handle, state);
                     }
                     Some(Err(e)) => {
                         println!("Error receiving IRC message: {}", e);
-                        break;
+                        break ConnectionEnd::Lost;
                     }
                     None => {
                         println!("IRC stream ended");
-                        break;
+                        break ConnectionEnd::Lost;
                     }
                 }
             }
 
+            _ = tokio::time::sleep(queue_delay), if !send_queue.is_empty() => {
+                flush_send_queue(&client, send_queue, app_handle, state);
+            }
+
             command = command_receiver.recv() => {
                 match command {
-                    Some(IrcCommand::SendMessage { room_id, message }) => {
-                        if let Err(e) = client.send_privmsg(&room_id, &message) {
-                            println!("Failed to send message: {}", e);
-                        } else {
-                            println!("Sent message to {}: {}", room_id, message);
-
-                            // Create our own message and add it to the room
-                            let current_username = {
-                                let irc_state = state.lock().unwrap();
-                                irc_state.current_username.clone().unwrap_or_default()
-                            };
-
-                            let our_message = IrcMessage {
-                                room_id: room_id.clone(),
-                                username: current_username,
-                                message: message.clone(),
-                                timestamp: std::time::SystemTime::now()
-                                    .duration_since(std::time::UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs(),
-                                is_private: false,
-                            };
-
-                            let (unread_count, is_active) = {
-                                let mut irc_state = state.lock().unwrap();
-                                let is_active = irc_state.active_room_id.as_ref() == Some(&room_id);
-                                if let Some(room) = irc_state.rooms.get_mut(&room_id) {
-                                    room.add_message(our_message.clone(), is_active);
-                                    (room.unread_count, is_active)
-                                } else {
-                                    (0, false)
-                                }
-                            };
-
-                            // Emit event based on room state
-                            if is_active {
-                                let _ = app_handle.emit("active-room-message", serde_json::json!({
-                                    "roomId": room_id,
-                                    "message": our_message
-                                }));
-                            } else {
-                                let _ = app_handle.emit("inactive-room-unread-updated", serde_json::json!({
-                                    "roomId": room_id,
-                                    "unreadCount": unread_count
-                                }));
-                            }
-                        }
-                    }
-                    Some(IrcCommand::SendPrivateMessage { username, message }) => {
-                        if let Err(e) = client.send_privmsg(&username, &message) {
-                            println!("Failed to send private message: {}", e);
-                        } else {
-                            println!("Sent private message to {}: {}", username, message);
-
-                            // Create our own message and add it to the PM room
-                            let current_username = {
-                                let irc_state = state.lock().unwrap();
-                                irc_state.current_username.clone().unwrap_or_default()
-                            };
-
-                            let our_message = IrcMessage {
-                                room_id: username.clone(),
-                                username: current_username,
-                                message: message.clone(),
-                                timestamp: std::time::SystemTime::now()
-                                    .duration_since(std::time::UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs(),
-                                is_private: true,
-                            };
-
-                            let (unread_count, is_active) = {
-                                let mut irc_state = state.lock().unwrap();
-                                let is_active = irc_state.active_room_id.as_ref() == Some(&username);
-                                if let Some(room) = irc_state.rooms.get_mut(&username) {
-                                    room.add_message(our_message.clone(), is_active);
-                                    (room.unread_count, is_active)
-                                } else {
-                                    (0, false)
-                                }
-                            };
-
-                            // Emit event based on room state
-                            if is_active {
-                                let _ = app_handle.emit("active-room-message", serde_json::json!({
-                                    "roomId": username,
-                                    "message": our_message
-                                }));
-                            } else {
-                                let _ = app_handle.emit("inactive-room-unread-updated", serde_json::json!({
-                                    "roomId": username,
-                                    "unreadCount": unread_count
-                                }));
-                            }
-                        }
-                    }
-                    Some(IrcCommand::JoinChannel { channel }) => {
-                        if let Err(e) = client.send_join(&channel) {
-                            println!("Failed to join channel {}: {}", channel, e);
-                        } else {
-                            println!("Joined channel: {}", channel);
-                        }
+                    Some(command @ (IrcCommand::SendMessage { .. } | IrcCommand::SendPrivateMessage { .. })) => {
+                        send_queue.push(command);
+                        flush_send_queue(&client, send_queue, app_handle, state);
                     }
-                    Some(IrcCommand::LeaveChannel { channel }) => {
-                        if let Err(e) = client.send_part(&channel) {
-                            println!("Failed to leave channel {}: {}", channel, e);
-                        } else {
-                            println!("Left channel: {}", channel);
-                        }
+                    Some(IrcCommand::SetRateLimit { config }) => {
+                        send_queue.configure(&config);
                     }
+                    Some(IrcCommand::JoinChannel { channel }) => join_channel(&client, &channel),
+                    Some(IrcCommand::LeaveChannel { channel }) => leave_channel(&client, &channel),
                     Some(IrcCommand::Disconnect) => {
                         println!("Disconnect command received");
                         let _ = client.send_quit("Goodbye!");
-                        break;
+                        break ConnectionEnd::Requested;
                     }
                     None => {
                         println!("Command channel closed");
-                        break;
+                        break ConnectionEnd::Requested;
                     }
                 }
             }
         }
-    }
+    };
 
     println!("IRC connection handler ended");
 
-    // Mark as disconnected
-    {
-        let mut irc_state = state.lock().unwrap();
-        irc_state.connected = false;
-        irc_state.client = None;
-        irc_state.message_sender = None;
+    end
+}
+
+fn join_channel(client: &irc::client::Client, channel: &str) {
+    if let Err(e) = client.send_join(channel) {
+        println!("Failed to join channel {}: {}", channel, e);
+    } else {
+        println!("Joined channel: {}", channel);
     }
+}
 
-    if let Err(e) = app_handle.emit("irc-disconnected", ()) {
-        println!("Failed to emit disconnect event: {}", e);
+fn leave_channel(client: &irc::client::Client, channel: &str) {
+    if let Err(e) = client.send_part(channel) {
+        println!("Failed to leave channel {}: {}", channel, e);
+    } else {
+        println!("Left channel: {}", channel);
+    }
+}
+
+/// Sends every queued message the rate limit currently allows and reports queue changes.
+fn flush_send_queue<R: Runtime>(
+    client: &irc::client::Client,
+    send_queue: &mut SendQueue,
+    app_handle: &tauri::AppHandle<R>,
+    state: &IrcState,
+) {
+    while let Some(command) = send_queue.pop_ready() {
+        match command {
+            IrcCommand::SendMessage {
+                room_id, message, ..
+            } => {
+                send_chat_message(client, &room_id, &message, false, app_handle, state);
+            }
+            IrcCommand::SendPrivateMessage { username, message } => {
+                send_chat_message(client, &username, &message, true, app_handle, state);
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(depth) = send_queue.depth_changed() {
+        let _ = app_handle.emit(
+            "send-queue-updated",
+            serde_json::json!({
+                "depth": depth,
+                "delayed": depth > 0
+            }),
+        );
+    }
+}
+
+/// Hides the argument of `!mp password`, it must not end up in the chat history.
+fn redact_password(message: &str) -> String {
+    match message.trim_start().strip_prefix("!mp password ") {
+        Some(password) if !password.trim().is_empty() => "!mp password ******".to_string(),
+        _ => message.to_string(),
+    }
+}
+
+fn log_message<R: Runtime>(message: &IrcMessage, app_handle: &tauri::AppHandle<R>) {
+    if let Some(chat_log) = app_handle.try_state::<ChatLog>() {
+        chat_log.record(message);
     }
 }
 
-fn handle_incoming_message(
+fn send_chat_message<R: Runtime>(
+    client: &irc::client::Client,
+    room_id: &str,
+    message: &str,
+    is_private: bool,
+    app_handle: &tauri::AppHandle<R>,
+    state: &IrcState,
+) {
+    if let Err(e) = client.send_privmsg(room_id, message) {
+        println!("Failed to send message: {}", e);
+        return;
+    }
+    // The room, the chat log and exports only get to see that a password was set
+    let message = redact_password(message);
+    println!("Sent message to {}: {}", room_id, message);
+
+    // Create our own message and add it to the room
+    let current_username = {
+        let irc_state = state.lock().unwrap();
+        irc_state.current_username.clone().unwrap_or_default()
+    };
+
+    let our_message = IrcMessage {
+        room_id: room_id.to_string(),
+        username: current_username,
+        message,
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        is_private,
+    };
+
+    let (unread_count, is_active) = {
+        let mut irc_state = state.lock().unwrap();
+        let is_active = irc_state.active_room_id.as_deref() == Some(room_id);
+        if let Some(room) = irc_state.rooms.get_mut(room_id) {
+            room.add_message(our_message.clone(), is_active);
+            (room.unread_count, is_active)
+        } else {
+            (0, false)
+        }
+    };
+
+    log_message(&our_message, app_handle);
+
+    // Emit event based on room state
+    if is_active {
+        let _ = app_handle.emit(
+            "active-room-message",
+            serde_json::json!({
+                "roomId": room_id,
+                "message": our_message
+            }),
+        );
+    } else {
+        let _ = app_handle.emit(
+            "inactive-room-unread-updated",
+            serde_json::json!({
+                "roomId": room_id,
+                "unreadCount": unread_count
+            }),
+        );
+    }
+}
+
+fn handle_incoming_message<R: Runtime>(
     msg: irc::proto::Message,
-    app_handle: &tauri::AppHandle,
+    app_handle: &tauri::AppHandle<R>,
     state: &IrcState,
 ) {
     match msg.command {
@@ -239,8 +519,12 @@ fn handle_incoming_message(
                     }
                 };
 
+                log_message(&irc_message, app_handle);
+
                 if room_id.starts_with("#mp_") {
-                    BanchoBotParser::parse_irc_message(&irc_message, state, app_handle);
+                    handle_lobby_message(&irc_message, state, app_handle);
+                } else if let Some(lobby_id) = handle_match_created(&irc_message, state) {
+                    println!("Created tournament match {}", lobby_id);
                 }
 
                 // Emit event based on room state
@@ -278,6 +562,14 @@ fn handle_incoming_message(
                             let new_room = Room::new_channel(channel.clone());
                             irc_state.rooms.insert(channel.clone(), new_room);
                             irc_state.active_room_id = Some(channel.clone());
+
+                            if channel.starts_with("#mp_") {
+                                tauri::async_runtime::spawn(restore_draft(
+                                    channel.clone(),
+                                    Arc::clone(state),
+                                    app_handle.clone(),
+                                ));
+                            }
                         }
                         true
                     } else {
@@ -326,12 +618,21 @@ fn handle_incoming_message(
                             .unwrap_or_default()
                             .to_lowercase()
                     {
-                        irc_state.rooms.remove(&channel);
-                        // Only clear active_room_id if the removed room was active
-                        if irc_state.active_room_id.as_deref() == Some(channel.as_str()) {
-                            irc_state.active_room_id = None;
+                        // Bancho parts us from closed lobbies, those stay for review
+                        if irc_state
+                            .rooms
+                            .get(&channel)
+                            .is_some_and(|room| room.closed)
+                        {
+                            false
+                        } else {
+                            irc_state.rooms.remove(&channel);
+                            // Only clear active_room_id if the removed room was active
+                            if irc_state.active_room_id.as_deref() == Some(channel.as_str()) {
+                                irc_state.active_room_id = None;
+                            }
+                            true
                         }
-                        true
                     } else {
                         false
                     }
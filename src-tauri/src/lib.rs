@@ -1,8 +1,34 @@
+/// Compiles a regex pattern once and reuses it for all subsequent calls.
+macro_rules! static_regex {
+    ($pattern:expr) => {{
+        static RE: ::std::sync::OnceLock<::regex::Regex> = ::std::sync::OnceLock::new();
+        RE.get_or_init(|| ::regex::Regex::new($pattern).expect("Invalid regex pattern"))
+    }};
+}
+
+mod auto_ref;
 mod banchobot_parser;
+mod beatmap_cache;
+mod chat_log;
 mod commands;
+mod credentials;
+mod database;
+mod draft;
 mod irc_handler;
+mod lobby_reducer;
+mod lobby_sync;
+mod mappool;
+mod match_export;
+mod match_history;
 mod migrations;
+#[cfg(test)]
+mod mock_bancho;
 mod osu_api;
+mod osu_client;
+mod qualifier;
+mod secrets;
+mod send_queue;
+mod settings;
 mod types;
 
 use base64::Engine;
@@ -10,7 +36,12 @@ use commands::*;
 use tauri::{Emitter, Manager};
 use tauri_plugin_deep_link::DeepLinkExt;
 
+use crate::beatmap_cache::BeatmapCache;
+use crate::chat_log::ChatLog;
+use crate::database::DATABASE_URL;
 use crate::migrations::get_migrations;
+use crate::osu_client::OsuApiClient;
+use crate::secrets::SecretStore;
 use crate::types::IrcState;
 
 #[cfg(target_os = "android")]
@@ -52,11 +83,12 @@ pub fn run() {
         .plugin(tauri_plugin_deep_link::init())
         .plugin(
             tauri_plugin_sql::Builder::default()
-                .add_migrations("sqlite:osu_reffer_database.db", migrations)
+                .add_migrations(DATABASE_URL, migrations)
                 .build(),
         )
         .plugin(tauri_plugin_opener::init())
         .manage(IrcState::default())
+        .manage(BeatmapCache::default())
         .invoke_handler(tauri::generate_handler![
             connect_to_bancho,
             reconnect_to_bancho,
@@ -66,19 +98,88 @@ pub fn run() {
             leave_channel,
             close_private_message,
             get_connection_status,
+            set_rate_limit,
             get_rooms_list,
             set_active_room,
             start_private_message,
+            create_lobby,
             fetch_beatmap_data,
+            fetch_beatmaps_batch,
+            get_beatmap_cache_ttl,
+            set_beatmap_cache_ttl,
             fetch_user_data,
             set_mappool,
+            get_mappools,
+            create_mappool,
+            update_mappool,
+            delete_mappool,
+            import_mappool,
+            get_mappool_beatmaps,
+            add_beatmap_to_pool,
+            update_beatmap_in_pool,
+            reorder_beatmaps,
+            delete_beatmap_from_pool,
+            select_pool_slot,
+            clear_selected_slot,
             set_map_drain_time,
+            set_match_best_of,
+            set_map_winner,
+            reset_rolls,
+            set_auto_ref,
+            start_qualifier,
+            pause_qualifier,
+            resume_qualifier,
+            skip_qualifier_map,
+            replay_qualifier_map,
+            stop_qualifier,
+            configure_draft,
+            record_draft_action,
+            undo_draft_action,
+            clear_draft,
             get_room_state,
             get_room_messages_page,
+            search_chat_messages,
+            export_match_log,
+            get_match_history,
+            get_match_history_entry,
+            delete_match_history_entry,
+            save_credentials,
+            get_credentials,
+            delete_credentials,
+            save_oauth_token,
+            connect_osu_account,
+            delete_oauth_token,
             check_for_updates,
             install_update,
         ])
         .setup(|app| {
+            app.manage(ChatLog::new(app.handle().clone()));
+            app.manage(SecretStore::new(app.path().app_local_data_dir()?));
+            app.manage(OsuApiClient::new(app.handle().clone()));
+
+            // The database was preloaded and migrated by the SQL plugin, move whatever
+            // older versions left in plain text before the login page reads it and load
+            // the saved settings.
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::block_on(async move {
+                let pool = match database::sqlite_pool(&app_handle).await {
+                    Ok(pool) => pool,
+                    Err(e) => {
+                        println!("Cannot move stored secrets: {}", e);
+                        return;
+                    }
+                };
+                let secrets = app_handle.state::<SecretStore>();
+                match credentials::move_plaintext_secrets(&pool, &secrets).await {
+                    Ok(0) => {}
+                    Ok(moved) => println!("Moved {} stored secrets out of the database", moved),
+                    Err(e) => println!("Failed to move stored secrets: {}", e),
+                }
+                if let Err(e) = app_handle.state::<BeatmapCache>().load_ttl(&pool).await {
+                    println!("Failed to load beatmap cache lifetime: {}", e);
+                }
+            });
+
             let app_handle = app.handle().clone();
             app.deep_link().on_open_url(move |event| {
                 let urls = event.urls();
@@ -0,0 +1,801 @@
+use crate::banchobot_parser::LobbyEvent;
+use crate::types::*;
+
+/// The results of a map that just finished, for the integration layer to announce.
+#[derive(Debug, Clone)]
+pub struct FinishedMap {
+    pub map_index: u32,
+    /// Sorted by score, highest first.
+    pub results: Vec<MapResult>,
+    /// The updated scoreline, only set in team lobbies.
+    pub scoreline: Option<MatchScoreline>,
+    /// Players that were still playing when the map finished.
+    pub did_not_finish: Vec<String>,
+}
+
+/// Something the integration layer should announce after an event was applied.
+#[derive(Debug, Clone)]
+pub enum LobbyOutcome {
+    MapFinished(FinishedMap),
+    RollDecided {
+        winner: String,
+        red: Roll,
+        blue: Roll,
+    },
+    RollTied {
+        value: u64,
+    },
+    /// The lobby's map or mods changed and now differ from the selected pool slot
+    /// differently than before (an empty list means they match again).
+    SlotMismatchChanged {
+        slot_code: String,
+        mismatches: Vec<String>,
+    },
+    LobbyClosed,
+}
+
+/// Applies a lobby event to the lobby state. `timestamp` is when the line was received,
+/// in seconds since the epoch.
+pub fn apply_event(
+    lobby: &mut LobbyState,
+    event: &LobbyEvent,
+    timestamp: u64,
+) -> Option<LobbyOutcome> {
+    match event {
+        LobbyEvent::RoomNameChanged { name } => {
+            settings_mut(lobby).room_name = name.clone();
+        }
+        LobbyEvent::TeamModeChanged {
+            team_mode,
+            win_condition,
+        } => {
+            let settings = settings_mut(lobby);
+            settings.team_mode = team_mode.clone();
+            settings.win_condition = win_condition.clone();
+        }
+        LobbyEvent::MatchSettingsChanged {
+            size,
+            team_mode,
+            win_condition,
+        } => {
+            let settings = settings_mut(lobby);
+            settings.team_mode = team_mode.clone();
+            settings.win_condition = win_condition.clone();
+            if let Some(size) = size {
+                lobby.size_known = true;
+                resize_slots(lobby, *size);
+            }
+        }
+        LobbyEvent::SizeChanged { size } => {
+            lobby.size_known = true;
+            resize_slots(lobby, *size);
+        }
+        LobbyEvent::PlayerCountReported { count } => {
+            // `!mp settings` doesn't tell the size, only who is in which slot. Without a
+            // size reply the lobby is as big as the slots it lists, the slot lines that
+            // follow grow it up to the highest one.
+            if !lobby.size_known {
+                let highest_taken = lobby
+                    .slots
+                    .iter()
+                    .filter(|slot| slot.player.is_some())
+                    .map(|slot| slot.id)
+                    .max()
+                    .unwrap_or(0);
+                resize_slots(lobby, (*count).max(highest_taken));
+            }
+        }
+        LobbyEvent::PasswordChanged { has_password } => {
+            settings_mut(lobby).has_password = *has_password;
+        }
+        LobbyEvent::LockChanged { locked } => {
+            settings_mut(lobby).locked = *locked;
+        }
+        LobbyEvent::SlotUpdated { slot, player } => {
+            set_slot(lobby, *slot, player.clone());
+        }
+        LobbyEvent::MapChanged { map, by_referee } => {
+            lobby.current_map = Some(map.clone());
+            if *by_referee {
+                lobby.map_changes = lobby.map_changes.wrapping_add(1);
+            }
+            // Everyone has to ready up again for the new map
+            if lobby.match_status == "ready" {
+                lobby.match_status = "idle".to_string();
+            }
+            // "No Map" stays until the next `!mp settings` slot line reports the player again
+            for player in players_mut(lobby) {
+                player.is_ready = false;
+            }
+            // Mods are usually set right after the map, so only the map is checked here
+            return check_selected_slot(lobby, false);
+        }
+        LobbyEvent::MapReported { map } => {
+            // The lobby was already on this map, so readiness stays as it is
+            lobby.current_map = Some(map.clone());
+            return check_selected_slot(lobby, false);
+        }
+        LobbyEvent::ModsChanged { mods, freemod } => {
+            lobby.selected_mods = mods.clone();
+            lobby.freemod = *freemod;
+            return check_selected_slot(lobby, true);
+        }
+        LobbyEvent::PlayerJoined {
+            username,
+            slot,
+            team,
+        } => {
+            set_slot(
+                lobby,
+                *slot,
+                Player {
+                    username: username.clone(),
+                    team: team.clone(),
+                    is_ready: false,
+                    is_playing: false,
+                    is_host: false,
+                    no_map: false,
+                    did_not_finish: false,
+                },
+            );
+        }
+        LobbyEvent::PlayerLeft { username } | LobbyEvent::PlayerKicked { username } => {
+            if let Some(slot) = lobby.slots.iter_mut().find(|slot| {
+                slot.player
+                    .as_ref()
+                    .is_some_and(|p| &p.username == username)
+            }) {
+                slot.player = None;
+            }
+        }
+        LobbyEvent::PlayerMoved { username, slot } => {
+            let player = lobby
+                .slots
+                .iter_mut()
+                .find(|s| s.player.as_ref().is_some_and(|p| &p.username == username))
+                .and_then(|s| s.player.take());
+
+            if let Some(player) = player {
+                set_slot(lobby, *slot, player);
+            }
+        }
+        LobbyEvent::PlayerChangedTeam { username, team } => {
+            for player in players_mut(lobby) {
+                if &player.username == username {
+                    player.team = Some(team.clone());
+                }
+            }
+        }
+        LobbyEvent::HostChanged { username } => {
+            lobby.host = Some(username.clone());
+            for player in players_mut(lobby) {
+                player.is_host = &player.username == username;
+            }
+        }
+        LobbyEvent::HostCleared => {
+            lobby.host = None;
+            for player in players_mut(lobby) {
+                player.is_host = false;
+            }
+        }
+        LobbyEvent::AllPlayersReady => {
+            lobby.match_status = "ready".to_string();
+            // Bancho doesn't count players without the map
+            for player in players_mut(lobby) {
+                player.is_ready = !player.no_map;
+            }
+        }
+        LobbyEvent::MatchStarted => {
+            lobby.match_status = "active".to_string();
+            lobby.maps_played += 1;
+            lobby.match_start_time = Some(timestamp);
+            for player in players_mut(lobby) {
+                player.is_playing = !player.no_map;
+                player.did_not_finish = false;
+            }
+        }
+        LobbyEvent::MatchAborted => {
+            set_idle(lobby);
+            for player in players_mut(lobby) {
+                player.is_playing = false;
+                player.is_ready = false;
+            }
+        }
+        LobbyEvent::PlayerFinished {
+            username,
+            score,
+            passed,
+        } => {
+            record_map_result(lobby, username, *score, *passed, timestamp);
+            for player in players_mut(lobby) {
+                if &player.username == username {
+                    player.is_playing = false;
+                }
+            }
+        }
+        LobbyEvent::MatchFinished => {
+            let finished = finish_map(lobby);
+            lobby.selected_slot = None;
+            lobby.slot_mismatches.clear();
+            set_idle(lobby);
+            return Some(LobbyOutcome::MapFinished(finished));
+        }
+        LobbyEvent::PlayerRolled { username, value } => {
+            return record_roll(lobby, username, *value);
+        }
+        LobbyEvent::CountdownStarted { seconds } => {
+            lobby.timer_start_time = Some(timestamp);
+            lobby.timer_duration = Some(*seconds);
+        }
+        LobbyEvent::CountdownEnded { .. } => {
+            lobby.timer_start_time = None;
+            lobby.timer_duration = None;
+        }
+        LobbyEvent::MatchClosed => {
+            set_idle(lobby);
+            lobby.match_status = "closed".to_string();
+            lobby.timer_start_time = None;
+            lobby.timer_duration = None;
+            return Some(LobbyOutcome::LobbyClosed);
+        }
+    }
+
+    None
+}
+
+fn check_selected_slot(lobby: &mut LobbyState, with_mods: bool) -> Option<LobbyOutcome> {
+    let slot = lobby.selected_slot.as_ref()?;
+    let mismatches = if with_mods {
+        slot.mismatches(lobby)
+    } else {
+        slot.map_mismatch(lobby).into_iter().collect()
+    };
+
+    if mismatches == lobby.slot_mismatches {
+        return None;
+    }
+    lobby.slot_mismatches = mismatches.clone();
+    Some(LobbyOutcome::SlotMismatchChanged {
+        slot_code: slot.slot_code.clone(),
+        mismatches,
+    })
+}
+
+fn settings_mut(lobby: &mut LobbyState) -> &mut LobbySettings {
+    lobby.settings.get_or_insert_with(|| LobbySettings {
+        room_name: String::new(),
+        team_mode: "HeadToHead".to_string(),
+        win_condition: "Score".to_string(),
+        size: 16,
+        has_password: false,
+        locked: false,
+    })
+}
+
+fn players_mut(lobby: &mut LobbyState) -> impl Iterator<Item = &mut Player> {
+    lobby
+        .slots
+        .iter_mut()
+        .filter_map(|slot| slot.player.as_mut())
+}
+
+fn set_slot(lobby: &mut LobbyState, slot_id: u8, player: Player) {
+    // A player in a slot we don't have means our size is out of date
+    if slot_id as usize > lobby.slots.len() {
+        resize_slots(lobby, slot_id);
+    }
+    if let Some(slot) = lobby.slots.iter_mut().find(|s| s.id == slot_id) {
+        slot.player = Some(player);
+    }
+}
+
+/// Resizes the slot list to the lobby size. Players in slots that went away move into
+/// free slots. When there are none left they keep slots past the size, until
+/// `!mp settings` says where they are.
+fn resize_slots(lobby: &mut LobbyState, size: u8) {
+    let size = size.clamp(1, MAX_LOBBY_SLOTS);
+    settings_mut(lobby).size = size;
+
+    if size as usize >= lobby.slots.len() {
+        let first_new = lobby.slots.len() as u8 + 1;
+        lobby
+            .slots
+            .extend((first_new..=size).map(|id| PlayerSlot { id, player: None }));
+        return;
+    }
+
+    let displaced: Vec<Player> = lobby
+        .slots
+        .drain(size as usize..)
+        .filter_map(|slot| slot.player)
+        .collect();
+    for player in displaced {
+        match lobby.slots.iter_mut().find(|slot| slot.player.is_none()) {
+            Some(slot) => slot.player = Some(player),
+            None => {
+                println!(
+                    "No free slot left for {} in a lobby of {}",
+                    player.username, size
+                );
+                let id = lobby.slots.len() as u8 + 1;
+                lobby.slots.push(PlayerSlot {
+                    id,
+                    player: Some(player),
+                });
+            }
+        }
+    }
+}
+
+fn set_idle(lobby: &mut LobbyState) {
+    lobby.match_status = "idle".to_string();
+    lobby.match_start_time = None;
+}
+
+fn record_map_result(
+    lobby: &mut LobbyState,
+    username: &str,
+    score: u64,
+    passed: bool,
+    timestamp: u64,
+) {
+    let team = lobby
+        .slots
+        .iter()
+        .filter_map(|slot| slot.player.as_ref())
+        .find(|player| player.username == username)
+        .and_then(|player| player.team.clone());
+
+    let mut mods = lobby.selected_mods.clone();
+    if lobby.freemod {
+        mods.push("Freemod".to_string());
+    }
+
+    lobby.map_results.push(MapResult {
+        map_index: lobby.maps_played,
+        username: username.to_string(),
+        score,
+        passed,
+        team,
+        beatmap_id: lobby.current_map.as_ref().map(|map| map.beatmap_id),
+        mods,
+        timestamp,
+    });
+}
+
+/// The team a roll counts for: the team whose captain rolled, or the roller's own team
+/// when no captains are set up in the draft.
+fn roll_team(lobby: &LobbyState, username: &str) -> Option<String> {
+    let captains = lobby.draft.as_ref().map(|draft| {
+        [
+            ("red", draft.config.red_captain.as_deref()),
+            ("blue", draft.config.blue_captain.as_deref()),
+        ]
+    });
+
+    match captains {
+        Some(captains) if captains.iter().any(|(_, captain)| captain.is_some()) => captains
+            .iter()
+            .find(|(_, captain)| captain.is_some_and(|c| c.eq_ignore_ascii_case(username)))
+            .map(|(team, _)| team.to_string()),
+        _ => lobby
+            .slots
+            .iter()
+            .filter_map(|slot| slot.player.as_ref())
+            .find(|player| player.username == username)
+            .and_then(|player| player.team.clone()),
+    }
+}
+
+fn record_roll(lobby: &mut LobbyState, username: &str, value: u64) -> Option<LobbyOutcome> {
+    if lobby.rolls.winner.is_some() {
+        return None;
+    }
+
+    let team = roll_team(lobby, username)?;
+    let roll = Some(Roll {
+        username: username.to_string(),
+        value,
+    });
+    let rolls = &mut lobby.rolls;
+    match team.as_str() {
+        "red" if rolls.red.is_none() => rolls.red = roll,
+        "blue" if rolls.blue.is_none() => rolls.blue = roll,
+        _ => return None,
+    }
+
+    let (Some(red), Some(blue)) = (rolls.red.clone(), rolls.blue.clone()) else {
+        return None;
+    };
+
+    if red.value == blue.value {
+        rolls.red = None;
+        rolls.blue = None;
+        rolls.ties += 1;
+        return Some(LobbyOutcome::RollTied { value: red.value });
+    }
+
+    let winner = if red.value > blue.value {
+        "red"
+    } else {
+        "blue"
+    };
+    rolls.winner = Some(winner.to_string());
+    Some(LobbyOutcome::RollDecided {
+        winner: winner.to_string(),
+        red,
+        blue,
+    })
+}
+
+/// Collects the sorted results of the map that was just played. In team lobbies
+/// the map is also added to the running scoreline.
+fn finish_map(lobby: &mut LobbyState) -> FinishedMap {
+    let mut results: Vec<MapResult> = lobby
+        .map_results
+        .iter()
+        .filter(|result| result.map_index == lobby.maps_played)
+        .cloned()
+        .collect();
+    results.sort_by_key(|result| std::cmp::Reverse(result.score));
+
+    let scoreline = match &lobby.settings {
+        Some(settings) if settings.team_mode == "TeamVs" && !results.is_empty() => {
+            lobby
+                .scoreline
+                .record_map(lobby.maps_played, &results, &settings.win_condition);
+            if let (Some(map), Some(draft)) = (lobby.scoreline.maps.last_mut(), &lobby.draft) {
+                map.picked_by = map
+                    .beatmap_id
+                    .and_then(|beatmap_id| draft.picked_by(beatmap_id))
+                    .map(str::to_string);
+            }
+            Some(lobby.scoreline.clone())
+        }
+        _ => None,
+    };
+
+    let mut did_not_finish = Vec::new();
+    for player in players_mut(lobby) {
+        player.did_not_finish = player.is_playing;
+        if player.is_playing {
+            did_not_finish.push(player.username.clone());
+        }
+        player.is_playing = false;
+        player.is_ready = false;
+    }
+
+    FinishedMap {
+        map_index: lobby.maps_played,
+        results,
+        scoreline,
+        did_not_finish,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::banchobot_parser::BanchoBotParser;
+
+    fn apply_all(lobby: &mut LobbyState, events: Vec<LobbyEvent>) -> Option<LobbyOutcome> {
+        events
+            .iter()
+            .fold(None, |_, event| apply_event(lobby, event, 1_700_000_000))
+    }
+
+    fn finished_map(outcome: Option<LobbyOutcome>) -> FinishedMap {
+        match outcome {
+            Some(LobbyOutcome::MapFinished(finished_map)) => finished_map,
+            other => panic!("expected a finished map, got {:?}", other),
+        }
+    }
+
+    fn rolled(username: &str, value: u64) -> LobbyEvent {
+        LobbyEvent::PlayerRolled {
+            username: username.to_string(),
+            value,
+        }
+    }
+
+    fn joined(username: &str, slot: u8, team: &str) -> LobbyEvent {
+        LobbyEvent::PlayerJoined {
+            username: username.to_string(),
+            slot,
+            team: Some(team.to_string()),
+        }
+    }
+
+    fn finished(username: &str, score: u64) -> LobbyEvent {
+        LobbyEvent::PlayerFinished {
+            username: username.to_string(),
+            score,
+            passed: true,
+        }
+    }
+
+    #[test]
+    fn tracks_players_across_slots() {
+        let mut lobby = LobbyState::new();
+        apply_all(
+            &mut lobby,
+            vec![
+                joined("Cookiezi", 1, "red"),
+                LobbyEvent::PlayerMoved {
+                    username: "Cookiezi".to_string(),
+                    slot: 4,
+                },
+                LobbyEvent::HostChanged {
+                    username: "Cookiezi".to_string(),
+                },
+            ],
+        );
+
+        assert!(lobby.slots[0].player.is_none());
+        let player = lobby.slots[3].player.as_ref().unwrap();
+        assert!(player.is_host);
+        assert_eq!(lobby.host.as_deref(), Some("Cookiezi"));
+
+        apply_event(
+            &mut lobby,
+            &LobbyEvent::PlayerLeft {
+                username: "Cookiezi".to_string(),
+            },
+            0,
+        );
+        assert!(lobby.slots.iter().all(|slot| slot.player.is_none()));
+    }
+
+    #[test]
+    fn flags_players_still_playing_at_the_end() {
+        let mut lobby = LobbyState::new();
+        apply_all(
+            &mut lobby,
+            vec![
+                joined("Cookiezi", 1, "red"),
+                joined("WhiteCat", 2, "blue"),
+                LobbyEvent::MatchStarted,
+                finished("Cookiezi", 1_000_000),
+            ],
+        );
+
+        let playing =
+            |lobby: &LobbyState, slot: usize| lobby.slots[slot].player.as_ref().unwrap().is_playing;
+        assert!(!playing(&lobby, 0));
+        assert!(playing(&lobby, 1));
+
+        let finished_map = finished_map(apply_all(&mut lobby, vec![LobbyEvent::MatchFinished]));
+        assert_eq!(finished_map.did_not_finish, vec!["WhiteCat"]);
+        assert!(lobby.slots[1].player.as_ref().unwrap().did_not_finish);
+        assert!(!playing(&lobby, 1));
+    }
+
+    #[test]
+    fn no_map_players_stay_unready_after_a_map_change() {
+        let mut lobby = LobbyState::new();
+        let no_map = crate::banchobot_parser::BanchoBotParser::parse_line(
+            "Slot 2  No Map    https://osu.ppy.sh/u/2 WhiteCat         [Team Blue]",
+        )
+        .unwrap();
+        let map = CurrentMap {
+            beatmap_id: 75,
+            title: "Disco Prince".to_string(),
+            artist: "Kenji Ninuma".to_string(),
+            difficulty: "Normal".to_string(),
+        };
+        apply_all(
+            &mut lobby,
+            vec![
+                joined("Cookiezi", 1, "red"),
+                no_map,
+                LobbyEvent::MapChanged {
+                    map,
+                    by_referee: true,
+                },
+                LobbyEvent::AllPlayersReady,
+            ],
+        );
+
+        let player = |lobby: &LobbyState, slot: usize| lobby.slots[slot].player.clone().unwrap();
+        assert!(player(&lobby, 0).is_ready);
+        assert!(player(&lobby, 1).no_map);
+        assert!(!player(&lobby, 1).is_ready);
+
+        apply_all(&mut lobby, vec![LobbyEvent::MatchStarted]);
+        assert!(player(&lobby, 0).is_playing);
+        assert!(!player(&lobby, 1).is_playing);
+    }
+
+    #[test]
+    fn resizing_keeps_displaced_players() {
+        let mut lobby = LobbyState::new();
+        apply_all(
+            &mut lobby,
+            vec![
+                joined("Cookiezi", 1, "red"),
+                joined("WhiteCat", 6, "blue"),
+                LobbyEvent::SizeChanged { size: 4 },
+            ],
+        );
+
+        assert_eq!(lobby.slots.len(), 4);
+        assert_eq!(lobby.settings.as_ref().unwrap().size, 4);
+        let moved = lobby.slots[1].player.as_ref().unwrap();
+        assert_eq!(moved.username, "WhiteCat");
+
+        apply_event(
+            &mut lobby,
+            &LobbyEvent::PlayerMoved {
+                username: "Cookiezi".to_string(),
+                slot: 8,
+            },
+            0,
+        );
+        assert_eq!(lobby.slots.len(), 8);
+        assert_eq!(lobby.slots[7].player.as_ref().unwrap().username, "Cookiezi");
+    }
+
+    #[test]
+    fn shrinking_a_full_lobby_keeps_everyone() {
+        let mut lobby = LobbyState::new();
+        let teams = ["red", "blue"];
+        let events = (1..=8)
+            .map(|slot| joined(&format!("Player{}", slot), slot, teams[slot as usize % 2]))
+            .chain([LobbyEvent::SizeChanged { size: 4 }])
+            .collect();
+        apply_all(&mut lobby, events);
+
+        assert_eq!(lobby.settings.as_ref().unwrap().size, 4);
+        let players = lobby
+            .slots
+            .iter()
+            .filter_map(|slot| slot.player.as_ref())
+            .count();
+        assert_eq!(players, 8);
+        assert!(lobby
+            .slots
+            .iter()
+            .enumerate()
+            .all(|(i, slot)| slot.id as usize == i + 1));
+    }
+
+    #[test]
+    fn settings_reply_sizes_a_lobby_of_unknown_size() {
+        let slot_line =
+            |line: &str| crate::banchobot_parser::BanchoBotParser::parse_line(line).unwrap();
+        let mut lobby = LobbyState::new();
+        apply_all(
+            &mut lobby,
+            vec![
+                LobbyEvent::PlayerCountReported { count: 2 },
+                slot_line("Slot 1  Ready     https://osu.ppy.sh/u/1 Cookiezi         [Team Red]"),
+                slot_line("Slot 6  Not Ready https://osu.ppy.sh/u/2 WhiteCat         [Team Blue]"),
+            ],
+        );
+        assert_eq!(lobby.slots.len(), 6);
+        assert_eq!(lobby.slots[5].player.as_ref().unwrap().username, "WhiteCat");
+
+        // A size reply is the real size, later settings replies don't shrink it
+        apply_all(
+            &mut lobby,
+            vec![
+                LobbyEvent::SizeChanged { size: 8 },
+                LobbyEvent::PlayerCountReported { count: 2 },
+            ],
+        );
+        assert_eq!(lobby.slots.len(), 8);
+    }
+
+    #[test]
+    fn finished_team_map_updates_scoreline() {
+        let mut lobby = LobbyState::new();
+        let finished_map = finished_map(apply_all(
+            &mut lobby,
+            vec![
+                LobbyEvent::TeamModeChanged {
+                    team_mode: "TeamVs".to_string(),
+                    win_condition: "ScoreV2".to_string(),
+                },
+                joined("RedPlayer", 1, "red"),
+                joined("BluePlayer", 2, "blue"),
+                LobbyEvent::MatchStarted,
+                finished("RedPlayer", 400_000),
+                finished("BluePlayer", 700_000),
+                LobbyEvent::MatchFinished,
+            ],
+        ));
+
+        assert_eq!(lobby.match_status, "idle");
+        assert_eq!(finished_map.map_index, 1);
+        assert_eq!(finished_map.results[0].username, "BluePlayer");
+        let scoreline = finished_map.scoreline.unwrap();
+        assert_eq!((scoreline.red_wins, scoreline.blue_wins), (0, 1));
+    }
+
+    #[test]
+    fn head_to_head_map_has_no_scoreline() {
+        let mut lobby = LobbyState::new();
+        let finished_map = finished_map(apply_all(
+            &mut lobby,
+            vec![
+                LobbyEvent::MatchStarted,
+                finished("Solo", 1_000),
+                LobbyEvent::MatchFinished,
+            ],
+        ));
+
+        assert_eq!(finished_map.results.len(), 1);
+        assert!(finished_map.scoreline.is_none());
+    }
+
+    #[test]
+    fn captain_rolls_decide_the_winner() {
+        let mut lobby = LobbyState::new();
+        apply_all(
+            &mut lobby,
+            vec![
+                joined("RedPlayer", 1, "red"),
+                joined("BluePlayer", 2, "blue"),
+            ],
+        );
+
+        assert!(apply_event(&mut lobby, &rolled("RedPlayer", 50), 0).is_none());
+        assert!(matches!(
+            apply_event(&mut lobby, &rolled("BluePlayer", 50), 0),
+            Some(LobbyOutcome::RollTied { value: 50 })
+        ));
+        assert!(lobby.rolls.red.is_none());
+        assert_eq!(lobby.rolls.ties, 1);
+
+        apply_event(&mut lobby, &rolled("BluePlayer", 20), 0);
+        // Rerolls before the other captain rolled are ignored
+        apply_event(&mut lobby, &rolled("BluePlayer", 99), 0);
+        assert!(matches!(
+            apply_event(&mut lobby, &rolled("RedPlayer", 70), 0),
+            Some(LobbyOutcome::RollDecided { ref winner, .. }) if winner == "red"
+        ));
+        assert_eq!(lobby.rolls.winner.as_deref(), Some("red"));
+        assert!(apply_event(&mut lobby, &rolled("BluePlayer", 100), 0).is_none());
+    }
+
+    #[test]
+    fn only_changed_beatmap_replies_count_as_map_changes() {
+        let mut lobby = LobbyState::new();
+        let line = |text: &str| BanchoBotParser::parse_line(text).unwrap();
+        apply_all(
+            &mut lobby,
+            vec![joined("Cookiezi", 1, "red"), LobbyEvent::AllPlayersReady],
+        );
+
+        // A `!mp settings` reply that arrives before BanchoBot answers our `!mp map`
+        apply_all(
+            &mut lobby,
+            vec![line(
+                "Beatmap: https://osu.ppy.sh/b/75 Kenji Ninuma - DISCO PRINCE [Normal]",
+            )],
+        );
+        assert_eq!(lobby.map_changes, 0);
+        assert_eq!(lobby.current_map.as_ref().unwrap().beatmap_id, 75);
+        assert_eq!(lobby.match_status, "ready");
+
+        // The host picking a map isn't the reply either
+        apply_all(
+            &mut lobby,
+            vec![line(
+                "Beatmap changed to: Kenji Ninuma - DISCO PRINCE [Normal] (https://osu.ppy.sh/b/75)",
+            )],
+        );
+        assert_eq!(lobby.map_changes, 0);
+
+        apply_all(
+            &mut lobby,
+            vec![
+                LobbyEvent::AllPlayersReady,
+                line("Changed beatmap to https://osu.ppy.sh/b/129891 xi - FREEDOM DiVE"),
+            ],
+        );
+        assert_eq!(lobby.map_changes, 1);
+        assert_eq!(lobby.current_map.as_ref().unwrap().beatmap_id, 129891);
+        assert_eq!(lobby.match_status, "idle");
+    }
+}
@@ -0,0 +1,354 @@
+use crate::auto_ref;
+use crate::banchobot_parser::{BanchoBotParser, LobbyEvent};
+use crate::database::sqlite_pool;
+use crate::draft::{load_draft, save_draft, SavedDraft};
+use crate::lobby_reducer::{apply_event, FinishedMap, LobbyOutcome};
+use crate::match_history::save_closed_lobby;
+use crate::qualifier::QualifierPhase;
+use crate::send_queue::SendPriority;
+use crate::types::*;
+use tauri::{Emitter, Runtime};
+
+/// Parses a chat message, applies any lobby event to the room's state and tells the
+/// frontend about it. Returns whether the message changed a lobby.
+pub fn handle_lobby_message<R: Runtime>(
+    message: &IrcMessage,
+    state: &IrcState,
+    app_handle: &tauri::AppHandle<R>,
+) -> bool {
+    match BanchoBotParser::parse(message) {
+        Some((channel, event)) => {
+            apply_lobby_event(&channel, &event, message.timestamp, state, app_handle)
+        }
+        None => false,
+    }
+}
+
+/// Handles BanchoBot's reply to `!mp make`: joins the new `#mp_` channel if Bancho
+/// hasn't put us there yet and hands the match id to a waiting `create_lobby`.
+/// Returns the new room id.
+pub fn handle_match_created(message: &IrcMessage, state: &IrcState) -> Option<String> {
+    if message.username != "BanchoBot" || !message.is_private {
+        return None;
+    }
+    let (match_id, name) = BanchoBotParser::parse_match_created(&message.message)?;
+    let room_id = format!("#mp_{}", match_id);
+
+    let mut irc_state = state.lock().unwrap();
+
+    if !irc_state.rooms.contains_key(&room_id) {
+        if let Some(sender) = &irc_state.message_sender {
+            let _ = sender.send(IrcCommand::JoinChannel {
+                channel: room_id.clone(),
+            });
+        }
+    }
+
+    if let Some(index) = irc_state
+        .pending_lobbies
+        .iter()
+        .position(|pending| pending.name == name)
+    {
+        let pending = irc_state.pending_lobbies.remove(index);
+        let _ = pending.created.send(match_id);
+    }
+
+    Some(room_id)
+}
+
+pub fn apply_lobby_event<R: Runtime>(
+    channel: &str,
+    event: &LobbyEvent,
+    timestamp: u64,
+    state: &IrcState,
+    app_handle: &tauri::AppHandle<R>,
+) -> bool {
+    let (outcome, auto_ref_messages, saved_draft) = {
+        let mut irc_state = state.lock().unwrap();
+        let is_active = irc_state.active_room_id.as_deref() == Some(channel);
+        let Some(room) = irc_state.rooms.get_mut(channel) else {
+            return false;
+        };
+        // A closed lobby keeps its final state
+        if room.closed {
+            return false;
+        }
+        if event == &LobbyEvent::MatchClosed {
+            room.closed = true;
+        }
+        let Some(lobby) = room.lobby_state.as_mut() else {
+            return false;
+        };
+
+        let outcome = apply_event(lobby, event, timestamp);
+        let finished_map = match &outcome {
+            Some(LobbyOutcome::MapFinished(finished_map)) => Some(finished_map),
+            _ => None,
+        };
+
+        // A qualifier run starts its own maps, auto-ref only takes over outside of one.
+        let auto_ref_messages = match (&mut lobby.qualifier, &lobby.auto_ref) {
+            (Some(run), _) if run.phase != QualifierPhase::Finished => {
+                run.on_event(event, finished_map)
+            }
+            (_, Some(config)) => auto_ref::react(config, lobby, event, timestamp),
+            _ => Vec::new(),
+        };
+        // The draft's turns depend on the scoreline, so they are saved together
+        let saved_draft = finished_map
+            .and_then(|finished_map| finished_map.scoreline.as_ref())
+            .and_then(|_| lobby.saved_draft());
+        let start_blocked = lobby.start_blocked();
+        let auto_ref_messages = auto_ref_messages
+            .into_iter()
+            .filter(|message| match &start_blocked {
+                Some(reason) if message.starts_with("!mp start") => {
+                    println!("Not starting {}: {}", channel, reason);
+                    false
+                }
+                _ => true,
+            })
+            .collect::<Vec<_>>();
+
+        if is_active {
+            let _ = app_handle.emit(
+                "active-room-lobby-state-updated",
+                serde_json::json!({ "lobbyState": lobby }),
+            );
+        }
+
+        (outcome, auto_ref_messages, saved_draft)
+    };
+
+    if let Some(saved) = saved_draft {
+        tauri::async_runtime::spawn(store_draft(channel.to_string(), saved, app_handle.clone()));
+    }
+
+    for message in auto_ref_messages {
+        announce(channel, message, state);
+    }
+
+    match outcome {
+        Some(LobbyOutcome::MapFinished(finished_map)) => {
+            emit_finished_map(channel, finished_map, app_handle)
+        }
+        Some(LobbyOutcome::RollDecided { winner, red, blue }) => {
+            announce(
+                channel,
+                format!(
+                    "{} wins the roll ({} {} - {} {})",
+                    capitalize(&winner),
+                    red.username,
+                    red.value,
+                    blue.value,
+                    blue.username
+                ),
+                state,
+            );
+            let _ = app_handle.emit(
+                "roll-decided",
+                serde_json::json!({
+                    "roomId": channel,
+                    "winner": winner,
+                    "red": red,
+                    "blue": blue
+                }),
+            );
+        }
+        Some(LobbyOutcome::RollTied { value }) => {
+            announce(
+                channel,
+                format!("Both captains rolled {}, please roll again", value),
+                state,
+            );
+            let _ = app_handle.emit(
+                "roll-tied",
+                serde_json::json!({ "roomId": channel, "value": value }),
+            );
+        }
+        Some(LobbyOutcome::SlotMismatchChanged {
+            slot_code,
+            mismatches,
+        }) => {
+            let _ = app_handle.emit(
+                "slot-mismatch",
+                serde_json::json!({
+                    "roomId": channel,
+                    "slotCode": slot_code,
+                    "mismatches": mismatches
+                }),
+            );
+        }
+        Some(LobbyOutcome::LobbyClosed) => {
+            let lobby = state
+                .lock()
+                .unwrap()
+                .rooms
+                .get(channel)
+                .and_then(|room| room.lobby_state.clone());
+            if let Some(lobby) = lobby {
+                tauri::async_runtime::spawn(archive_closed_lobby(
+                    channel.to_string(),
+                    lobby,
+                    app_handle.clone(),
+                ));
+            }
+            let _ = app_handle.emit("lobby-closed", serde_json::json!({ "roomId": channel }));
+            emit_rooms_list(state, app_handle);
+        }
+        None => {}
+    }
+
+    true
+}
+
+/// Sends a message to the lobby on the referee's behalf.
+fn announce(channel: &str, message: String, state: &IrcState) {
+    let irc_state = state.lock().unwrap();
+    if let Some(sender) = &irc_state.message_sender {
+        let _ = sender.send(IrcCommand::SendMessage {
+            room_id: channel.to_string(),
+            priority: SendPriority::of(&message),
+            message,
+        });
+    }
+}
+
+fn capitalize(team: &str) -> String {
+    let mut chars = team.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn emit_finished_map<R: Runtime>(
+    channel: &str,
+    finished_map: FinishedMap,
+    app_handle: &tauri::AppHandle<R>,
+) {
+    if let Some(scoreline) = finished_map.scoreline {
+        let _ = app_handle.emit(
+            "team-score-updated",
+            serde_json::json!({
+                "roomId": channel,
+                "scoreline": scoreline
+            }),
+        );
+    }
+
+    let _ = app_handle.emit(
+        "map-finished",
+        serde_json::json!({
+            "roomId": channel,
+            "mapIndex": finished_map.map_index,
+            "beatmapId": finished_map.results.first().and_then(|r| r.beatmap_id),
+            "results": finished_map.results,
+            "didNotFinish": finished_map.did_not_finish
+        }),
+    );
+}
+
+fn emit_rooms_list<R: Runtime>(state: &IrcState, app_handle: &tauri::AppHandle<R>) {
+    let rooms_response = {
+        let irc_state = state.lock().unwrap();
+        RoomsListResponse {
+            rooms: irc_state.rooms.values().map(RoomListItem::from).collect(),
+            active_room_id: irc_state.active_room_id.clone(),
+        }
+    };
+    let _ = app_handle.emit("rooms-list-updated", rooms_response);
+}
+
+/// Moves a closed lobby into the match history. Its draft is part of the archived
+/// state, so the saved copy isn't needed anymore.
+async fn archive_closed_lobby<R: Runtime>(
+    room_id: String,
+    lobby: LobbyState,
+    app_handle: tauri::AppHandle<R>,
+) {
+    let pool = match sqlite_pool(&app_handle).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            println!("Cannot archive {}: {}", room_id, e);
+            return;
+        }
+    };
+
+    match save_closed_lobby(&pool, &room_id, &lobby).await {
+        Ok(id) => {
+            let _ = app_handle.emit(
+                "match-history-updated",
+                serde_json::json!({ "roomId": room_id, "id": id }),
+            );
+        }
+        Err(e) => {
+            println!("Failed to archive {}: {}", room_id, e);
+            return;
+        }
+    }
+
+    if let Err(e) = save_draft(&pool, &room_id, None).await {
+        println!("Failed to delete draft of {}: {}", room_id, e);
+    }
+}
+
+async fn store_draft<R: Runtime>(
+    room_id: String,
+    saved: SavedDraft,
+    app_handle: tauri::AppHandle<R>,
+) {
+    let result = match sqlite_pool(&app_handle).await {
+        Ok(pool) => save_draft(&pool, &room_id, Some(&saved))
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        println!("Failed to save draft of {}: {}", room_id, e);
+    }
+}
+
+/// Loads the saved pick/ban draft and scoreline of a lobby we just joined, so a match
+/// can carry on after a restart.
+pub async fn restore_draft<R: Runtime>(
+    room_id: String,
+    state: IrcState,
+    app_handle: tauri::AppHandle<R>,
+) {
+    let pool = match sqlite_pool(&app_handle).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            println!("Cannot restore draft for {}: {}", room_id, e);
+            return;
+        }
+    };
+
+    let saved = match load_draft(&pool, &room_id).await {
+        Ok(Some(saved)) => saved,
+        Ok(None) => return,
+        Err(e) => {
+            println!("Failed to load draft for {}: {}", room_id, e);
+            return;
+        }
+    };
+
+    let mut irc_state = state.lock().unwrap();
+    let is_active = irc_state.active_room_id.as_deref() == Some(room_id.as_str());
+    if let Some(lobby) = irc_state
+        .rooms
+        .get_mut(&room_id)
+        .and_then(|room| room.lobby_state.as_mut())
+    {
+        if lobby.draft.is_none() {
+            lobby.draft = Some(saved.draft);
+            lobby.scoreline = saved.scoreline;
+            if is_active {
+                let _ = app_handle.emit(
+                    "active-room-lobby-state-updated",
+                    serde_json::json!({ "lobbyState": lobby }),
+                );
+            }
+        }
+    }
+}
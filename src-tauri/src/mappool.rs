@@ -0,0 +1,463 @@
+use crate::types::LobbyState;
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+// Pools keep the column names as field names, that's the shape the mappool pages use.
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct Mappool {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct BeatmapEntry {
+    pub id: i64,
+    pub mappool_id: i64,
+    pub beatmap_id: i64,
+    pub artist: String,
+    pub title: String,
+    pub difficulty: String,
+    pub mapper: String,
+    pub mod_combination: Option<String>,
+    pub category: Option<String>,
+    pub created_at: String,
+    pub position: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NewBeatmapEntry {
+    pub beatmap_id: i64,
+    pub artist: String,
+    pub title: String,
+    pub difficulty: String,
+    pub mapper: String,
+    pub mod_combination: Option<String>,
+    pub category: Option<String>,
+}
+
+const ENTRY_COLUMNS: &str = "id, mappool_id, beatmap_id, artist, title, difficulty, mapper, \
+                             mod_combination, category, created_at, position";
+
+/// Finds the entry a slot code like "NM1", "HD2" or "TB" refers to. Entries whose
+/// category is the whole code win, otherwise the number counts entries of that
+/// category in pool order.
+pub fn find_slot<'a>(entries: &'a [BeatmapEntry], code: &str) -> Option<&'a BeatmapEntry> {
+    let code = code.trim().to_uppercase();
+    let category_is = |entry: &&BeatmapEntry, category: &str| {
+        entry
+            .category
+            .as_deref()
+            .is_some_and(|c| c.eq_ignore_ascii_case(category))
+    };
+
+    if let Some(entry) = entries.iter().find(|entry| category_is(entry, &code)) {
+        return Some(entry);
+    }
+
+    let digits = code.len() - code.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (category, number) = code.split_at(code.len() - digits);
+    let number: usize = if number.is_empty() {
+        1
+    } else {
+        number.parse().ok()?
+    };
+
+    entries
+        .iter()
+        .filter(|entry| category_is(entry, category))
+        .nth(number.checked_sub(1)?)
+}
+
+/// The forced mods and freemod flag a pool's mod combination like "HDHR" or "FM"
+/// asks for, with NF added when `no_fail` is set.
+pub fn slot_mods(mod_combination: Option<&str>, no_fail: bool) -> (Vec<String>, bool) {
+    let combination = mod_combination.unwrap_or("").trim().to_uppercase();
+    let mut mods: Vec<String> = Vec::new();
+    let mut freemod = false;
+
+    if combination != "NONE" {
+        for chunk in combination.as_bytes().chunks(2) {
+            match String::from_utf8_lossy(chunk).as_ref() {
+                "NM" => {}
+                "FM" => freemod = true,
+                other => mods.push(other.to_string()),
+            }
+        }
+    }
+
+    if no_fail && !mods.iter().any(|m| m == "NF") {
+        mods.insert(0, "NF".to_string());
+    }
+
+    (mods, freemod)
+}
+
+/// Turns a pool's mod combination into `!mp mods` arguments, see `slot_mods`.
+pub fn mods_argument(mod_combination: Option<&str>, no_fail: bool) -> String {
+    let (mut mods, freemod) = slot_mods(mod_combination, no_fail);
+    if freemod {
+        mods.push("Freemod".to_string());
+    }
+
+    if mods.is_empty() {
+        "None".to_string()
+    } else {
+        mods.join(" ")
+    }
+}
+
+/// The pool slot a referee put up, which the lobby is checked against until the map
+/// is played.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectedSlot {
+    pub slot_code: String,
+    pub beatmap_id: u64,
+    pub mods: Vec<String>,
+    pub freemod: bool,
+}
+
+impl SelectedSlot {
+    pub fn new(slot_code: &str, entry: &BeatmapEntry, no_fail: bool) -> Self {
+        let (mods, freemod) = slot_mods(entry.mod_combination.as_deref(), no_fail);
+        Self {
+            slot_code: slot_code.trim().to_uppercase(),
+            beatmap_id: entry.beatmap_id as u64,
+            mods,
+            freemod,
+        }
+    }
+
+    pub fn map_mismatch(&self, lobby: &LobbyState) -> Option<String> {
+        match &lobby.current_map {
+            Some(map) if map.beatmap_id == self.beatmap_id => None,
+            Some(map) => Some(format!(
+                "The lobby is on beatmap {} instead of {}",
+                map.beatmap_id, self.beatmap_id
+            )),
+            None => Some(format!("Beatmap {} is not selected", self.beatmap_id)),
+        }
+    }
+
+    pub fn mods_mismatch(&self, lobby: &LobbyState) -> Option<String> {
+        let mut expected = self.mods.clone();
+        let mut actual = lobby.selected_mods.clone();
+        expected.sort();
+        actual.sort();
+
+        if expected != actual {
+            return Some(format!(
+                "Mods are {} instead of {}",
+                mods_label(&lobby.selected_mods),
+                mods_label(&self.mods)
+            ));
+        }
+        if self.freemod != lobby.freemod {
+            return Some(if self.freemod {
+                "Freemod is off".to_string()
+            } else {
+                "Freemod is on".to_string()
+            });
+        }
+        None
+    }
+
+    /// Everything that differs between the lobby and this slot.
+    pub fn mismatches(&self, lobby: &LobbyState) -> Vec<String> {
+        self.map_mismatch(lobby)
+            .into_iter()
+            .chain(self.mods_mismatch(lobby))
+            .collect()
+    }
+}
+
+fn mods_label(mods: &[String]) -> String {
+    if mods.is_empty() {
+        "NM".to_string()
+    } else {
+        mods.join("")
+    }
+}
+
+/// Same format as JavaScript's `toISOString`, which wrote the existing rows.
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Empty strings from the forms are stored as NULL.
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|v| !v.trim().is_empty())
+}
+
+pub async fn list_mappools(pool: &SqlitePool) -> Result<Vec<Mappool>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT id, name, description, created_at, updated_at
+         FROM mappools
+         ORDER BY updated_at DESC",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn create_mappool(
+    pool: &SqlitePool,
+    name: &str,
+    description: Option<String>,
+) -> Result<i64, sqlx::Error> {
+    let now = now();
+    let result = sqlx::query(
+        "INSERT INTO mappools (name, description, created_at, updated_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(name)
+    .bind(non_empty(description))
+    .bind(&now)
+    .bind(&now)
+    .execute(pool)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn update_mappool(
+    pool: &SqlitePool,
+    id: i64,
+    name: &str,
+    description: Option<String>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE mappools SET name = ?, description = ?, updated_at = ? WHERE id = ?")
+        .bind(name)
+        .bind(non_empty(description))
+        .bind(now())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_mappool(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM mappools WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// The beatmaps of a pool in the order the referee arranged them.
+pub async fn pool_beatmaps(
+    pool: &SqlitePool,
+    mappool_id: i64,
+) -> Result<Vec<BeatmapEntry>, sqlx::Error> {
+    sqlx::query_as(&format!(
+        "SELECT {} FROM beatmap_entries
+         WHERE mappool_id = ?
+         ORDER BY position ASC, created_at ASC, id ASC",
+        ENTRY_COLUMNS
+    ))
+    .bind(mappool_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Appends a beatmap to the end of a pool.
+pub async fn add_beatmap(
+    pool: &SqlitePool,
+    mappool_id: i64,
+    entry: NewBeatmapEntry,
+) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO beatmap_entries
+         (mappool_id, beatmap_id, artist, title, difficulty, mapper, mod_combination, category, created_at, position)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?,
+           (SELECT COALESCE(MAX(position), -1) + 1 FROM beatmap_entries WHERE mappool_id = ?))",
+    )
+    .bind(mappool_id)
+    .bind(entry.beatmap_id)
+    .bind(entry.artist)
+    .bind(entry.title)
+    .bind(entry.difficulty)
+    .bind(entry.mapper)
+    .bind(non_empty(entry.mod_combination))
+    .bind(non_empty(entry.category))
+    .bind(now())
+    .bind(mappool_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+/// Creates a pool with all its beatmaps, or nothing if any insert fails.
+pub async fn import_mappool(
+    pool: &SqlitePool,
+    name: &str,
+    entries: Vec<NewBeatmapEntry>,
+) -> Result<i64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let now = now();
+
+    let mappool_id = sqlx::query(
+        "INSERT INTO mappools (name, description, created_at, updated_at) VALUES (?, NULL, ?, ?)",
+    )
+    .bind(name)
+    .bind(&now)
+    .bind(&now)
+    .execute(&mut *tx)
+    .await?
+    .last_insert_rowid();
+
+    for (position, entry) in entries.into_iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO beatmap_entries
+             (mappool_id, beatmap_id, artist, title, difficulty, mapper, mod_combination, category, created_at, position)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(mappool_id)
+        .bind(entry.beatmap_id)
+        .bind(entry.artist)
+        .bind(entry.title)
+        .bind(entry.difficulty)
+        .bind(entry.mapper)
+        .bind(non_empty(entry.mod_combination))
+        .bind(non_empty(entry.category))
+        .bind(&now)
+        .bind(position as i64)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(mappool_id)
+}
+
+pub async fn update_beatmap(
+    pool: &SqlitePool,
+    id: i64,
+    category: Option<String>,
+    mod_combination: Option<String>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE beatmap_entries SET category = ?, mod_combination = ? WHERE id = ?")
+        .bind(non_empty(category))
+        .bind(non_empty(mod_combination))
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Gives each entry its index in `ordered_ids` as its position.
+pub async fn reorder_beatmaps(pool: &SqlitePool, ordered_ids: &[i64]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for (position, id) in ordered_ids.iter().enumerate() {
+        sqlx::query("UPDATE beatmap_entries SET position = ? WHERE id = ?")
+            .bind(position as i64)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+pub async fn delete_beatmap(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM beatmap_entries WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::banchobot_parser::BanchoBotParser;
+    use crate::lobby_reducer::apply_event;
+
+    fn entry(id: i64, category: &str) -> BeatmapEntry {
+        BeatmapEntry {
+            id,
+            mappool_id: 1,
+            beatmap_id: id * 100,
+            artist: String::new(),
+            title: String::new(),
+            difficulty: String::new(),
+            mapper: String::new(),
+            mod_combination: None,
+            category: Some(category.to_string()),
+            created_at: String::new(),
+            position: id,
+        }
+    }
+
+    #[test]
+    fn finds_slots_by_code_or_position() {
+        let numbered = [entry(1, "NM1"), entry(2, "NM2"), entry(3, "TB")];
+        assert_eq!(find_slot(&numbered, "nm2").unwrap().id, 2);
+        assert_eq!(find_slot(&numbered, "TB").unwrap().id, 3);
+
+        let bare = [entry(1, "NM"), entry(2, "HD"), entry(3, "NM")];
+        assert_eq!(find_slot(&bare, "NM2").unwrap().id, 3);
+        assert_eq!(find_slot(&bare, "HD1").unwrap().id, 2);
+        assert!(find_slot(&bare, "NM3").is_none());
+        assert!(find_slot(&bare, "DT1").is_none());
+    }
+
+    #[test]
+    fn builds_mods_arguments() {
+        assert_eq!(mods_argument(None, false), "None");
+        assert_eq!(mods_argument(Some("NM"), true), "NF");
+        assert_eq!(mods_argument(Some("HDHR"), false), "HD HR");
+        assert_eq!(mods_argument(Some("HD"), true), "NF HD");
+        assert_eq!(mods_argument(Some("FM"), true), "NF Freemod");
+        assert_eq!(mods_argument(Some("None"), false), "None");
+    }
+
+    #[test]
+    fn compares_the_lobby_with_the_selected_slot() {
+        let mut hidden = entry(1, "HD1");
+        hidden.mod_combination = Some("HD".to_string());
+        let slot = SelectedSlot::new("hd1", &hidden, true);
+        assert_eq!(slot.mods, vec!["NF", "HD"]);
+
+        let mut lobby = LobbyState::new();
+        assert_eq!(slot.mismatches(&lobby).len(), 2);
+
+        lobby.current_map = Some(crate::types::CurrentMap {
+            beatmap_id: 100,
+            title: String::new(),
+            difficulty: String::new(),
+            artist: String::new(),
+        });
+        lobby.selected_mods = vec!["HD".to_string(), "NF".to_string()];
+        assert!(slot.mismatches(&lobby).is_empty());
+
+        lobby.freemod = true;
+        assert_eq!(slot.mismatches(&lobby), vec!["Freemod is on"]);
+    }
+
+    #[test]
+    fn freemod_slot_matches_banchobots_reply() {
+        let mut freemod = entry(3, "FM1");
+        freemod.mod_combination = Some("FM".to_string());
+        assert_eq!(mods_argument(Some("FM"), true), "NF Freemod");
+
+        let mut lobby = LobbyState::new();
+        lobby.selected_slot = Some(SelectedSlot::new("FM1", &freemod, true));
+        for line in [
+            "Changed beatmap to https://osu.ppy.sh/b/300 Artist - Title [Insane]",
+            "Enabled NoFail, enabled FreeMod",
+        ] {
+            let event = BanchoBotParser::parse_line(line).unwrap();
+            apply_event(&mut lobby, &event, 0);
+        }
+
+        assert_eq!(lobby.selected_mods, vec!["NF"]);
+        assert!(lobby.freemod);
+        assert!(lobby.slot_mismatches.is_empty());
+        assert_eq!(lobby.start_blocked(), None);
+    }
+}
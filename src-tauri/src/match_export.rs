@@ -0,0 +1,359 @@
+use crate::banchobot_parser::BanchoBotParser;
+use crate::types::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Text,
+    Json,
+    Markdown,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Text => "txt",
+            ExportFormat::Json => "json",
+            ExportFormat::Markdown => "md",
+        }
+    }
+}
+
+/// A lobby event recognised from a BanchoBot line.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchLogEvent {
+    pub timestamp: u64,
+    pub kind: String,
+    pub description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchLog {
+    pub room_id: String,
+    pub exported_at: u64,
+    pub lobby_state: Option<LobbyState>,
+    pub messages: Vec<IrcMessage>,
+    pub events: Vec<MatchLogEvent>,
+}
+
+impl MatchLog {
+    pub fn new(
+        room_id: String,
+        lobby_state: Option<LobbyState>,
+        messages: Vec<IrcMessage>,
+    ) -> Self {
+        let events = messages
+            .iter()
+            .filter(|message| message.username == "BanchoBot")
+            .filter_map(|message| {
+                let (_, event) = BanchoBotParser::parse(message)?;
+                // Lines from `!mp settings` only describe the lobby, they aren't events
+                if matches!(event.kind(), "settings" | "slot") {
+                    return None;
+                }
+                Some(MatchLogEvent {
+                    timestamp: message.timestamp,
+                    kind: event.kind().to_string(),
+                    description: message.message.clone(),
+                })
+            })
+            .collect();
+
+        Self {
+            room_id,
+            exported_at: Utc::now().timestamp() as u64,
+            lobby_state,
+            messages,
+            events,
+        }
+    }
+
+    pub fn render(&self, format: ExportFormat) -> Result<String, String> {
+        match format {
+            ExportFormat::Text => Ok(self.to_text()),
+            ExportFormat::Json => serde_json::to_string_pretty(self)
+                .map_err(|e| format!("Failed to serialize match log: {}", e)),
+            ExportFormat::Markdown => Ok(self.to_markdown()),
+        }
+    }
+
+    pub fn file_name(&self, format: ExportFormat) -> String {
+        format!(
+            "{}_{}.{}",
+            self.room_id.trim_start_matches('#'),
+            format_timestamp(self.exported_at, "%Y%m%d-%H%M%S"),
+            format.extension()
+        )
+    }
+
+    fn title(&self) -> String {
+        self.lobby_state
+            .as_ref()
+            .and_then(|lobby| lobby.settings.as_ref())
+            .map(|settings| settings.room_name.clone())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| self.room_id.clone())
+    }
+
+    /// Plain IRC style log, one line per message.
+    fn to_text(&self) -> String {
+        let mut out = String::new();
+        for message in &self.messages {
+            let _ = writeln!(
+                out,
+                "[{}] <{}> {}",
+                format_timestamp(message.timestamp, "%Y-%m-%d %H:%M:%S"),
+                message.username,
+                message.message
+            );
+        }
+        out
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# {}\n", self.title());
+
+        let mp_id = self.room_id.trim_start_matches("#mp_");
+        let _ = writeln!(
+            out,
+            "- Room: `{}` ([match history](https://osu.ppy.sh/mp/{}))",
+            self.room_id, mp_id
+        );
+
+        let lobby = self.lobby_state.as_ref();
+        if let Some(settings) = lobby.and_then(|lobby| lobby.settings.as_ref()) {
+            let _ = writeln!(out, "- Team mode: {}", settings.team_mode);
+            let _ = writeln!(out, "- Win condition: {}", settings.win_condition);
+        }
+        let _ = writeln!(
+            out,
+            "- Exported: {} UTC",
+            format_timestamp(self.exported_at, "%Y-%m-%d %H:%M:%S")
+        );
+
+        if let Some(lobby) = lobby {
+            let scoreline = &lobby.scoreline;
+            if !scoreline.maps.is_empty() {
+                let _ = writeln!(
+                    out,
+                    "\n## Scoreline\n\n**Red {} - {} Blue**",
+                    scoreline.red_wins, scoreline.blue_wins
+                );
+                if let Some(winner) = &scoreline.match_winner {
+                    let _ = writeln!(out, "\nWinner: {}", winner);
+                }
+            }
+
+            if !lobby.map_results.is_empty() {
+                let _ = writeln!(out, "\n## Maps");
+                for map_index in 1..=lobby.maps_played {
+                    let mut results: Vec<&MapResult> = lobby
+                        .map_results
+                        .iter()
+                        .filter(|result| result.map_index == map_index)
+                        .collect();
+                    if results.is_empty() {
+                        continue;
+                    }
+                    results.sort_by_key(|result| std::cmp::Reverse(result.score));
+
+                    let first = results[0];
+                    let beatmap = first
+                        .beatmap_id
+                        .map(|id| format!("https://osu.ppy.sh/b/{}", id))
+                        .unwrap_or_else(|| "unknown beatmap".to_string());
+                    let mods = if first.mods.is_empty() {
+                        "NM".to_string()
+                    } else {
+                        first.mods.join(", ")
+                    };
+                    let _ = writeln!(out, "\n### Map {} - {} ({})\n", map_index, beatmap, mods);
+
+                    if let Some(team_score) = lobby
+                        .scoreline
+                        .maps
+                        .iter()
+                        .find(|map| map.map_index == map_index)
+                    {
+                        let _ = writeln!(
+                            out,
+                            "Red {} - {} Blue, winner: {}\n",
+                            team_score.red_score,
+                            team_score.blue_score,
+                            team_score.winner.as_deref().unwrap_or("none")
+                        );
+                    }
+
+                    let _ = writeln!(out, "| Player | Team | Score | Result |");
+                    let _ = writeln!(out, "| --- | --- | ---: | --- |");
+                    for result in results {
+                        let _ = writeln!(
+                            out,
+                            "| {} | {} | {} | {} |",
+                            result.username,
+                            result.team.as_deref().unwrap_or("-"),
+                            result.score,
+                            if result.passed { "PASSED" } else { "FAILED" }
+                        );
+                    }
+                }
+            }
+        }
+
+        if !self.events.is_empty() {
+            let _ = writeln!(out, "\n## Events\n");
+            for event in &self.events {
+                let _ = writeln!(
+                    out,
+                    "- `{}` {}",
+                    format_timestamp(event.timestamp, "%H:%M:%S"),
+                    event.description
+                );
+            }
+        }
+
+        out
+    }
+}
+
+fn format_timestamp(timestamp: u64, format: &str) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
+        .map(|date| date.format(format).to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lobby_reducer::apply_event;
+
+    const START: u64 = 1_700_000_000;
+
+    fn match_log() -> MatchLog {
+        let lines = [
+            (
+                "BanchoBot",
+                "Room name: OWC: (A) vs (B), History: https://osu.ppy.sh/mp/123",
+            ),
+            ("BanchoBot", "Team mode: TeamVs, Win condition: ScoreV2"),
+            (
+                "BanchoBot",
+                "Slot 1  Not Ready https://osu.ppy.sh/u/124493 Cookiezi         [Team Red]",
+            ),
+            (
+                "BanchoBot",
+                "Beatmap: https://osu.ppy.sh/b/75 Kenji Ninuma - DISCO PRINCE [Normal]",
+            ),
+            ("BanchoBot", "peppy joined in slot 2 for team blue."),
+            ("referee", "glhf"),
+            ("BanchoBot", "The match has started!"),
+            (
+                "BanchoBot",
+                "Cookiezi finished playing (Score: 700000, PASSED).",
+            ),
+            (
+                "BanchoBot",
+                "peppy finished playing (Score: 400000, FAILED).",
+            ),
+            ("BanchoBot", "The match has finished!"),
+        ];
+        let messages: Vec<IrcMessage> = lines
+            .iter()
+            .enumerate()
+            .map(|(i, (username, message))| IrcMessage {
+                room_id: "#mp_123".to_string(),
+                username: username.to_string(),
+                message: message.to_string(),
+                timestamp: START + i as u64,
+                is_private: false,
+            })
+            .collect();
+
+        let mut lobby = LobbyState::new();
+        for message in &messages {
+            if let Some((_, event)) = BanchoBotParser::parse(message) {
+                apply_event(&mut lobby, &event, message.timestamp);
+            }
+        }
+
+        let mut log = MatchLog::new("#mp_123".to_string(), Some(lobby), messages);
+        log.exported_at = START;
+        log
+    }
+
+    #[test]
+    fn leaves_settings_lines_out_of_the_events() {
+        let log = match_log();
+        let kinds: Vec<&str> = log.events.iter().map(|event| event.kind.as_str()).collect();
+        assert_eq!(kinds, vec!["join", "start", "result", "result", "finish"]);
+    }
+
+    #[test]
+    fn renders_text_one_line_per_message() {
+        let text = match_log().render(ExportFormat::Text).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), 10);
+        assert_eq!(
+            lines[0],
+            "[2023-11-14 22:13:20] <BanchoBot> Room name: OWC: (A) vs (B), History: https://osu.ppy.sh/mp/123"
+        );
+        assert_eq!(lines[5], "[2023-11-14 22:13:25] <referee> glhf");
+    }
+
+    #[test]
+    fn renders_markdown_scoreline_and_map_tables() {
+        let markdown = match_log().render(ExportFormat::Markdown).unwrap();
+
+        assert!(markdown.starts_with("# OWC: (A) vs (B)\n"));
+        assert!(
+            markdown.contains("- Room: `#mp_123` ([match history](https://osu.ppy.sh/mp/123))\n")
+        );
+        assert!(markdown.contains("## Scoreline\n\n**Red 1 - 0 Blue**\n"));
+        assert!(markdown.contains(
+            "### Map 1 - https://osu.ppy.sh/b/75 (NM)\n\n\
+             Red 700000 - 400000 Blue, winner: red\n\n\
+             | Player | Team | Score | Result |\n\
+             | --- | --- | ---: | --- |\n\
+             | Cookiezi | red | 700000 | PASSED |\n\
+             | peppy | blue | 400000 | FAILED |\n"
+        ));
+        assert!(markdown.ends_with(
+            "## Events\n\n\
+             - `22:13:24` peppy joined in slot 2 for team blue.\n\
+             - `22:13:26` The match has started!\n\
+             - `22:13:27` Cookiezi finished playing (Score: 700000, PASSED).\n\
+             - `22:13:28` peppy finished playing (Score: 400000, FAILED).\n\
+             - `22:13:29` The match has finished!\n"
+        ));
+    }
+
+    #[test]
+    fn json_export_reads_back() {
+        let log = match_log();
+        let json = log.render(ExportFormat::Json).unwrap();
+        let read_back: MatchLog = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(read_back.room_id, log.room_id);
+        assert_eq!(read_back.exported_at, START);
+        assert_eq!(read_back.messages.len(), log.messages.len());
+        assert_eq!(read_back.messages[5].message, "glhf");
+        assert_eq!(read_back.events.len(), log.events.len());
+        assert_eq!(read_back.events[1].kind, "start");
+
+        let lobby = read_back.lobby_state.unwrap();
+        assert_eq!(lobby.map_results.len(), 2);
+        assert_eq!(lobby.scoreline.red_wins, 1);
+        assert_eq!(lobby.scoreline.maps[0].winner.as_deref(), Some("red"));
+        assert_eq!(
+            log.file_name(ExportFormat::Json),
+            "mp_123_20231114-221320.json"
+        );
+    }
+}
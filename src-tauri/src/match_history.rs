@@ -0,0 +1,133 @@
+use crate::types::LobbyState;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+/// A closed lobby as listed in the match history.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchHistorySummary {
+    pub id: i64,
+    pub room_id: String,
+    pub room_name: Option<String>,
+    pub maps_played: u32,
+    pub red_wins: u32,
+    pub blue_wins: u32,
+    pub closed_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchHistoryEntry {
+    #[serde(flatten)]
+    pub summary: MatchHistorySummary,
+    /// The lobby as it was when BanchoBot closed it.
+    pub lobby_state: LobbyState,
+}
+
+type SummaryRow = (i64, String, Option<String>, i64, i64, i64, String);
+/// A summary row followed by the serialized lobby state.
+type EntryRow = (i64, String, Option<String>, i64, i64, i64, String, String);
+
+fn summary_from_row(
+    (id, room_id, room_name, maps_played, red_wins, blue_wins, closed_at): SummaryRow,
+) -> MatchHistorySummary {
+    MatchHistorySummary {
+        id,
+        room_id,
+        room_name,
+        maps_played: maps_played as u32,
+        red_wins: red_wins as u32,
+        blue_wins: blue_wins as u32,
+        closed_at,
+    }
+}
+
+/// Stores the final state of a closed lobby and returns the new history id.
+pub async fn save_closed_lobby(
+    pool: &SqlitePool,
+    room_id: &str,
+    lobby: &LobbyState,
+) -> Result<i64, sqlx::Error> {
+    let json = serde_json::to_string(lobby).unwrap_or_default();
+    let room_name = lobby
+        .settings
+        .as_ref()
+        .map(|settings| settings.room_name.clone())
+        .filter(|name| !name.is_empty());
+
+    let result = sqlx::query(
+        "INSERT INTO match_history
+         (room_id, room_name, maps_played, red_wins, blue_wins, lobby_state, closed_at)
+         VALUES (?, ?, ?, ?, ?, ?, datetime('now'))",
+    )
+    .bind(room_id)
+    .bind(room_name)
+    .bind(lobby.maps_played as i64)
+    .bind(lobby.scoreline.red_wins as i64)
+    .bind(lobby.scoreline.blue_wins as i64)
+    .bind(json)
+    .execute(pool)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+/// Closed lobbies, most recently closed first.
+pub async fn list_match_history(
+    pool: &SqlitePool,
+) -> Result<Vec<MatchHistorySummary>, sqlx::Error> {
+    let rows: Vec<SummaryRow> = sqlx::query_as(
+        "SELECT id, room_id, room_name, maps_played, red_wins, blue_wins, closed_at
+         FROM match_history
+         ORDER BY closed_at DESC, id DESC",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(summary_from_row).collect())
+}
+
+pub async fn load_match_history(
+    pool: &SqlitePool,
+    id: i64,
+) -> Result<Option<MatchHistoryEntry>, sqlx::Error> {
+    let row: Option<EntryRow> = sqlx::query_as(
+        "SELECT id, room_id, room_name, maps_played, red_wins, blue_wins, closed_at, lobby_state
+         FROM match_history
+         WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.and_then(
+        |(id, room_id, room_name, maps_played, red_wins, blue_wins, closed_at, lobby_state)| {
+            match serde_json::from_str(&lobby_state) {
+                Ok(lobby_state) => Some(MatchHistoryEntry {
+                    summary: summary_from_row((
+                        id,
+                        room_id,
+                        room_name,
+                        maps_played,
+                        red_wins,
+                        blue_wins,
+                        closed_at,
+                    )),
+                    lobby_state,
+                }),
+                Err(e) => {
+                    println!("Ignoring unreadable match history entry {}: {}", id, e);
+                    None
+                }
+            }
+        },
+    ))
+}
+
+pub async fn delete_match_history(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM match_history WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -97,5 +97,94 @@ pub fn get_migrations() -> Vec<Migration> {
         ",
             kind: MigrationKind::Up,
         },
+        Migration {
+            version: 7,
+            description: "create_chat_messages_with_fts",
+            sql: "
+            CREATE TABLE IF NOT EXISTS chat_messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                room_id TEXT NOT NULL,
+                username TEXT NOT NULL,
+                message TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                is_private INTEGER NOT NULL DEFAULT 0
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_chat_messages_room_id ON chat_messages(room_id, id);
+
+            CREATE VIRTUAL TABLE IF NOT EXISTS chat_messages_fts USING fts5(
+                message,
+                username,
+                room_id UNINDEXED,
+                content='chat_messages',
+                content_rowid='id'
+            );
+
+            CREATE TRIGGER IF NOT EXISTS chat_messages_ai AFTER INSERT ON chat_messages BEGIN
+                INSERT INTO chat_messages_fts(rowid, message, username, room_id)
+                VALUES (new.id, new.message, new.username, new.room_id);
+            END;
+
+            CREATE TRIGGER IF NOT EXISTS chat_messages_ad AFTER DELETE ON chat_messages BEGIN
+                INSERT INTO chat_messages_fts(chat_messages_fts, rowid, message, username, room_id)
+                VALUES ('delete', old.id, old.message, old.username, old.room_id);
+            END;
+        ",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 8,
+            description: "create_match_drafts",
+            sql: "
+            CREATE TABLE IF NOT EXISTS match_drafts (
+                room_id TEXT PRIMARY KEY,
+                draft TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
+        ",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 9,
+            description: "create_match_history",
+            sql: "
+            CREATE TABLE IF NOT EXISTS match_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                room_id TEXT NOT NULL,
+                room_name TEXT,
+                maps_played INTEGER NOT NULL,
+                red_wins INTEGER NOT NULL,
+                blue_wins INTEGER NOT NULL,
+                lobby_state TEXT NOT NULL,
+                closed_at TEXT NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_match_history_closed_at ON match_history(closed_at);
+        ",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 10,
+            description: "create_beatmap_cache",
+            sql: "
+            CREATE TABLE IF NOT EXISTS beatmap_cache (
+                beatmap_id INTEGER PRIMARY KEY,
+                data TEXT NOT NULL,
+                fetched_at INTEGER NOT NULL
+            );
+        ",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 11,
+            description: "create_settings",
+            sql: "
+            CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+        ",
+            kind: MigrationKind::Up,
+        },
     ]
 }
@@ -0,0 +1,780 @@
+//! A scripted fake Bancho IRC server for integration tests.
+//!
+//! It accepts the usual PASS/NICK/USER handshake and answers the `!mp` commands
+//! a referee sends with the same lines BanchoBot would, so tests can drive the
+//! real connection handler and assert on the resulting `LobbyState` and events.
+
+use crate::irc_handler::{connect_client, handle_irc_connection, ConnectionEnd};
+use crate::send_queue::{RateLimitConfig, SendPriority, SendQueue};
+use crate::types::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::test::{mock_app, MockRuntime};
+use tauri::{App, Listener, Manager};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+const BANCHOBOT: &str = ":BanchoBot!cho@ppy.sh";
+
+#[derive(Clone)]
+pub struct MockPlayer {
+    pub slot: u8,
+    pub username: String,
+    pub team: Option<&'static str>, // "Red" or "Blue"
+    pub ready: bool,
+    pub host: bool,
+}
+
+#[derive(Clone)]
+pub struct MockLobby {
+    pub name: String,
+    pub team_mode: &'static str,
+    pub win_condition: &'static str,
+    pub beatmap: (u64, &'static str, &'static str, &'static str),
+    pub players: Vec<MockPlayer>,
+    /// Lines sent as `<username> finished playing ...` after `!mp start`.
+    pub results: Vec<(String, u64, bool)>,
+}
+
+impl MockLobby {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            team_mode: "HeadToHead",
+            win_condition: "Score",
+            beatmap: (75, "Kenji Ninuma", "DISCO PRINCE", "Normal"),
+            players: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+
+    fn settings_lines(&self, match_id: u64) -> Vec<String> {
+        let (beatmap_id, artist, title, difficulty) = self.beatmap;
+        let mut lines = vec![
+            format!(
+                "Room name: {}, History: https://osu.ppy.sh/mp/{}",
+                self.name, match_id
+            ),
+            format!(
+                "Beatmap: https://osu.ppy.sh/b/{} {} - {} [{}]",
+                beatmap_id, artist, title, difficulty
+            ),
+            format!(
+                "Team mode: {}, Win condition: {}",
+                self.team_mode, self.win_condition
+            ),
+            "Active mods: Freemod".to_string(),
+            format!("Players: {}", self.players.len()),
+        ];
+
+        for player in &self.players {
+            let mut tags = Vec::new();
+            if player.host {
+                tags.push("Host".to_string());
+            }
+            if let Some(team) = player.team {
+                tags.push(format!("Team {}", team));
+            }
+            lines.push(format!(
+                "Slot {:<2} {:<9} https://osu.ppy.sh/u/{} {:<16} [{}]",
+                player.slot,
+                if player.ready { "Ready" } else { "Not Ready" },
+                1000 + player.slot as u64,
+                player.username,
+                tags.join(" / ")
+            ));
+        }
+
+        lines
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    nick: String,
+    next_match_id: u64,
+    lobbies: HashMap<u64, MockLobby>,
+}
+
+pub struct MockBancho {
+    pub port: u16,
+    state: Arc<Mutex<MockState>>,
+    received: Arc<Mutex<Vec<String>>>,
+    outgoing: UnboundedSender<String>,
+}
+
+impl MockBancho {
+    /// Starts the server on a random localhost port. It serves a single client connection.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let state = Arc::new(Mutex::new(MockState {
+            next_match_id: 1,
+            ..MockState::default()
+        }));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let (outgoing, mut outgoing_rx) = unbounded_channel::<String>();
+
+        let server_state = Arc::clone(&state);
+        let server_received = Arc::clone(&received);
+        let reply = outgoing.clone();
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+
+            loop {
+                tokio::select! {
+                    line = lines.next_line() => {
+                        let Ok(Some(line)) = line else {
+                            break;
+                        };
+                        server_received.lock().unwrap().push(line.clone());
+                        for response in respond(&line, &server_state) {
+                            let _ = reply.send(response);
+                        }
+                    }
+                    line = outgoing_rx.recv() => {
+                        let Some(line) = line else {
+                            break;
+                        };
+                        if line == "DROP" {
+                            break;
+                        }
+                        if writer.write_all(format!("{}\r\n", line).as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            port,
+            state,
+            received,
+            outgoing,
+        }
+    }
+
+    /// Registers a lobby that already exists on the server, e.g. to test joining `#mp_<id>`.
+    pub fn add_lobby(&self, match_id: u64, lobby: MockLobby) {
+        let mut state = self.state.lock().unwrap();
+        state.lobbies.insert(match_id, lobby);
+        state.next_match_id = state.next_match_id.max(match_id + 1);
+    }
+
+    /// Sends a line from BanchoBot to a channel.
+    pub fn bancho_says(&self, channel: &str, text: &str) {
+        let _ = self
+            .outgoing
+            .send(format!("{} PRIVMSG {} :{}", BANCHOBOT, channel, text));
+    }
+
+    /// Closes the client connection as if Bancho dropped us.
+    pub fn drop_connection(&self) {
+        let _ = self.outgoing.send("DROP".to_string());
+    }
+
+    /// All raw lines the client has sent so far.
+    pub fn received(&self) -> Vec<String> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+fn respond(line: &str, state: &Arc<Mutex<MockState>>) -> Vec<String> {
+    let mut state = state.lock().unwrap();
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+    match command {
+        "NICK" => {
+            state.nick = rest.to_string();
+            vec![format!(
+                ":cho.ppy.sh 001 {} :Welcome to the osu!Bancho.",
+                state.nick
+            )]
+        }
+        "PING" => vec![format!(":cho.ppy.sh PONG {}", rest)],
+        "JOIN" => {
+            let channel = rest.trim_start_matches(':');
+            vec![format!(":{}!cho@ppy.sh JOIN :{}", state.nick, channel)]
+        }
+        "PART" => {
+            let channel = rest.trim_start_matches(':');
+            vec![format!(":{}!cho@ppy.sh PART :{}", state.nick, channel)]
+        }
+        "PRIVMSG" => {
+            let Some((target, text)) = rest.split_once(" :") else {
+                return Vec::new();
+            };
+            respond_to_privmsg(target, text, &mut state)
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn respond_to_privmsg(target: &str, text: &str, state: &mut MockState) -> Vec<String> {
+    let nick = state.nick.clone();
+
+    if target == "BanchoBot" {
+        if let Some(name) = text.strip_prefix("!mp make ") {
+            let match_id = state.next_match_id;
+            state.next_match_id += 1;
+            state.lobbies.insert(match_id, MockLobby::new(name));
+            return vec![
+                format!(":{}!cho@ppy.sh JOIN :#mp_{}", nick, match_id),
+                format!(
+                    "{} PRIVMSG {} :Created the tournament match https://osu.ppy.sh/mp/{} {}",
+                    BANCHOBOT, nick, match_id, name
+                ),
+            ];
+        }
+        return Vec::new();
+    }
+
+    let Some(match_id) = target
+        .strip_prefix("#mp_")
+        .and_then(|id| id.parse::<u64>().ok())
+    else {
+        return Vec::new();
+    };
+    let Some(lobby) = state.lobbies.get(&match_id) else {
+        return Vec::new();
+    };
+
+    let say = |text: &str| format!("{} PRIVMSG {} :{}", BANCHOBOT, target, text);
+    let args: Vec<&str> = text.split_whitespace().collect();
+
+    match args.as_slice() {
+        ["!mp", "settings"] => lobby
+            .settings_lines(match_id)
+            .iter()
+            .map(|line| say(line))
+            .collect(),
+        ["!mp", "start", ..] => {
+            let mut lines = vec![say("The match has started!")];
+            for (username, score, passed) in &lobby.results {
+                lines.push(say(&format!(
+                    "{} finished playing (Score: {}, {}).",
+                    username,
+                    score,
+                    if *passed { "PASSED" } else { "FAILED" }
+                )));
+            }
+            if !lobby.results.is_empty() {
+                lines.push(say("The match has finished!"));
+            }
+            lines
+        }
+        ["!mp", "abort"] => vec![say("Aborted the match")],
+        ["!mp", "map", beatmap_id, ..] => vec![say(&format!(
+            "Changed beatmap to https://osu.ppy.sh/b/{} Mock Artist - Mock Title",
+            beatmap_id
+        ))],
+        ["!mp", "mods", "Freemod"] => vec![say("Disabled all mods, enabled FreeMod")],
+        ["!mp", "mods", mods @ ..] => {
+            let freemod = mods.iter().any(|m| m.eq_ignore_ascii_case("Freemod"));
+            let names: Vec<&str> = mods
+                .iter()
+                .filter(|m| !m.eq_ignore_ascii_case("Freemod"))
+                .map(|m| match m.to_uppercase().as_str() {
+                    "HD" => "Hidden",
+                    "HR" => "HardRock",
+                    "DT" => "DoubleTime",
+                    "NF" => "NoFail",
+                    "EZ" => "Easy",
+                    "FL" => "Flashlight",
+                    _ => "None",
+                })
+                .collect();
+            vec![say(&format!(
+                "Enabled {}, {} FreeMod",
+                names.join(", "),
+                if freemod { "enabled" } else { "disabled" }
+            ))]
+        }
+        ["!mp", "timer", seconds] => vec![say(&format!("Countdown ends in {} seconds", seconds))],
+        ["!mp", "host", username] => vec![say(&format!("Changed match host to {}", username))],
+        ["!mp", "password"] => vec![say("Removed the match password")],
+        ["!mp", "password", ..] => vec![say("Changed the match password")],
+        ["!mp", "lock"] => vec![say("Locked the match")],
+        ["!mp", "unlock"] => vec![say("Unlocked the match")],
+        ["!mp", "size", size] => vec![say(&format!("Changed match to size {}", size))],
+        ["!mp", "set", team_mode, win_condition, size @ ..] => {
+            let team_mode = ["HeadToHead", "TagCoop", "TeamVs", "TagTeamVs"]
+                .get(team_mode.parse::<usize>().unwrap_or(0))
+                .unwrap_or(&"HeadToHead");
+            let win_condition = ["Score", "Accuracy", "Combo", "ScoreV2"]
+                .get(win_condition.parse::<usize>().unwrap_or(0))
+                .unwrap_or(&"Score");
+            let size = size
+                .first()
+                .map(|size| format!("{} slots, ", size))
+                .unwrap_or_default();
+            vec![say(&format!(
+                "Changed match settings to {}{}, {}",
+                size, team_mode, win_condition
+            ))]
+        }
+        ["!mp", "close"] => vec![
+            say("Closed the match"),
+            format!(":{}!cho@ppy.sh PART :{}", nick, target),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// The app side of a test: a mock Tauri app wired to a real connection handler.
+pub struct TestClient {
+    pub app: App<MockRuntime>,
+    pub state: IrcState,
+    pub sender: UnboundedSender<IrcCommand>,
+    events: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+    handler: tokio::task::JoinHandle<ConnectionEnd>,
+}
+
+/// Events recorded by `TestClient`.
+const RECORDED_EVENTS: &[&str] = &[
+    "is-authenticated",
+    "rooms-list-updated",
+    "active-room-message",
+    "inactive-room-unread-updated",
+    "active-room-lobby-state-updated",
+    "map-finished",
+    "team-score-updated",
+    "send-queue-updated",
+    "room-error",
+    "lobby-closed",
+];
+
+impl TestClient {
+    pub async fn connect(bancho: &MockBancho) -> Self {
+        let app = mock_app();
+        let state: IrcState = Arc::new(Mutex::new(IrcClientState::default()));
+        app.manage(Arc::clone(&state));
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        for name in RECORDED_EVENTS {
+            let events = Arc::clone(&events);
+            let name = name.to_string();
+            app.listen_any(name.clone(), move |event| {
+                let payload = serde_json::from_str(event.payload()).unwrap_or_default();
+                events.lock().unwrap().push((name.clone(), payload));
+            });
+        }
+
+        let client = connect_client(&ConnectionConfig {
+            username: "TestRef".to_string(),
+            password: "irc-password".to_string(),
+            server: Some("127.0.0.1".to_string()),
+            port: Some(bancho.port),
+            use_tls: Some(false),
+        })
+        .await
+        .unwrap();
+
+        let (sender, mut receiver) = unbounded_channel::<IrcCommand>();
+        {
+            let mut irc_state = state.lock().unwrap();
+            irc_state.connected = true;
+            irc_state.current_username = Some("TestRef".to_string());
+            irc_state.message_sender = Some(sender.clone());
+        }
+
+        let app_handle = app.handle().clone();
+        let handler_state = Arc::clone(&state);
+        let handler = tokio::spawn(async move {
+            let mut send_queue = SendQueue::new(&RateLimitConfig {
+                burst: 50,
+                messages_per_second: 50.0,
+            });
+            handle_irc_connection(
+                client,
+                &app_handle,
+                &handler_state,
+                &mut receiver,
+                &mut send_queue,
+            )
+            .await
+        });
+
+        Self {
+            app,
+            state,
+            sender,
+            events,
+            handler,
+        }
+    }
+
+    pub fn say(&self, room_id: &str, message: &str) {
+        let command = if room_id.starts_with('#') {
+            IrcCommand::SendMessage {
+                room_id: room_id.to_string(),
+                message: message.to_string(),
+                priority: SendPriority::of(message),
+            }
+        } else {
+            IrcCommand::SendPrivateMessage {
+                username: room_id.to_string(),
+                message: message.to_string(),
+            }
+        };
+        self.sender.send(command).unwrap();
+    }
+
+    pub fn join(&self, channel: &str) {
+        self.sender
+            .send(IrcCommand::JoinChannel {
+                channel: channel.to_string(),
+            })
+            .unwrap();
+    }
+
+    pub fn lobby(&self, room_id: &str) -> Option<LobbyState> {
+        let irc_state = self.state.lock().unwrap();
+        irc_state
+            .rooms
+            .get(room_id)
+            .and_then(|room| room.lobby_state.clone())
+    }
+
+    pub fn events(&self, name: &str) -> Vec<serde_json::Value> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(event, _)| event == name)
+            .map(|(_, payload)| payload.clone())
+            .collect()
+    }
+
+    /// Polls the app state until `condition` holds, failing the test after five seconds.
+    pub async fn wait_until<F>(&self, description: &str, condition: F)
+    where
+        F: Fn(&TestClient) -> bool,
+    {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while !condition(self) {
+            if tokio::time::Instant::now() > deadline {
+                panic!("Timed out waiting for: {}", description);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    pub async fn disconnect(self) -> ConnectionEnd {
+        let _ = self.sender.send(IrcCommand::Disconnect);
+        self.handler.await.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(slot: u8, username: &str, team: Option<&'static str>) -> MockPlayer {
+        MockPlayer {
+            slot,
+            username: username.to_string(),
+            team,
+            ready: false,
+            host: false,
+        }
+    }
+
+    fn team_lobby() -> MockLobby {
+        let mut lobby = MockLobby::new("OWC: (Germany) vs (Poland)");
+        lobby.team_mode = "TeamVs";
+        lobby.win_condition = "ScoreV2";
+        lobby.players = vec![
+            MockPlayer {
+                host: true,
+                ..player(1, "RedOne", Some("Red"))
+            },
+            player(2, "RedTwo", Some("Red")),
+            player(3, "BlueOne", Some("Blue")),
+            player(4, "BlueTwo", Some("Blue")),
+        ];
+        lobby.results = vec![
+            ("RedOne".to_string(), 600_000, true),
+            ("RedTwo".to_string(), 300_000, false),
+            ("BlueOne".to_string(), 450_000, true),
+            ("BlueTwo".to_string(), 400_000, true),
+        ];
+        lobby
+    }
+
+    async fn joined_lobby(lobby: MockLobby) -> (MockBancho, TestClient) {
+        let bancho = MockBancho::start().await;
+        bancho.add_lobby(1, lobby);
+        let client = TestClient::connect(&bancho).await;
+
+        client.join("#mp_1");
+        client
+            .wait_until("joining #mp_1", |c| c.lobby("#mp_1").is_some())
+            .await;
+
+        (bancho, client)
+    }
+
+    #[tokio::test]
+    async fn handshake_authenticates() {
+        let bancho = MockBancho::start().await;
+        let client = TestClient::connect(&bancho).await;
+
+        client
+            .wait_until("welcome", |c| !c.events("is-authenticated").is_empty())
+            .await;
+
+        assert_eq!(
+            client.events("is-authenticated")[0],
+            serde_json::json!(true)
+        );
+        let received = bancho.received();
+        assert!(received.contains(&"PASS irc-password".to_string()));
+        assert!(received.contains(&"NICK TestRef".to_string()));
+
+        assert!(matches!(
+            client.disconnect().await,
+            ConnectionEnd::Requested
+        ));
+    }
+
+    #[tokio::test]
+    async fn mp_settings_rebuilds_lobby_state() {
+        let (_bancho, client) = joined_lobby(team_lobby()).await;
+
+        client.say("#mp_1", "!mp settings");
+        client
+            .wait_until("all slots parsed", |c| {
+                c.lobby("#mp_1")
+                    .map(|lobby| lobby.slots.iter().filter(|s| s.player.is_some()).count() == 4)
+                    .unwrap_or(false)
+            })
+            .await;
+
+        let lobby = client.lobby("#mp_1").unwrap();
+        let settings = lobby.settings.as_ref().unwrap();
+        assert_eq!(settings.room_name, "OWC: (Germany) vs (Poland)");
+        assert_eq!(settings.team_mode, "TeamVs");
+        assert_eq!(settings.win_condition, "ScoreV2");
+        assert_eq!(lobby.current_map.as_ref().unwrap().beatmap_id, 75);
+        assert!(lobby.freemod);
+
+        let host = lobby.slots[0].player.as_ref().unwrap();
+        assert_eq!(host.username, "RedOne");
+        assert_eq!(host.team.as_deref(), Some("red"));
+        assert!(host.is_host);
+        assert_eq!(
+            lobby.slots[2].player.as_ref().unwrap().team.as_deref(),
+            Some("blue")
+        );
+
+        assert!(!client.events("active-room-lobby-state-updated").is_empty());
+    }
+
+    #[tokio::test]
+    async fn finished_map_records_results_and_team_score() {
+        let (_bancho, client) = joined_lobby(team_lobby()).await;
+
+        client.say("#mp_1", "!mp settings");
+        client
+            .wait_until("settings parsed", |c| {
+                c.lobby("#mp_1")
+                    .map(|lobby| lobby.slots[3].player.is_some())
+                    .unwrap_or(false)
+            })
+            .await;
+
+        client.say("#mp_1", "!mp start");
+        client
+            .wait_until("map finished", |c| !c.events("map-finished").is_empty())
+            .await;
+
+        let lobby = client.lobby("#mp_1").unwrap();
+        assert_eq!(lobby.match_status, "idle");
+        assert_eq!(lobby.maps_played, 1);
+        assert_eq!(lobby.map_results.len(), 4);
+        assert!(
+            !lobby
+                .map_results
+                .iter()
+                .find(|r| r.username == "RedTwo")
+                .unwrap()
+                .passed
+        );
+
+        let finished = &client.events("map-finished")[0];
+        assert_eq!(finished["roomId"], "#mp_1");
+        assert_eq!(finished["results"][0]["username"], "RedOne");
+
+        assert_eq!(lobby.scoreline.red_wins, 1);
+        assert_eq!(lobby.scoreline.blue_wins, 0);
+        assert_eq!(lobby.scoreline.maps[0].red_score, 900_000);
+        assert_eq!(lobby.scoreline.maps[0].blue_score, 850_000);
+        assert_eq!(client.events("team-score-updated").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn lobby_events_update_slots_host_and_mods() {
+        let (bancho, client) = joined_lobby(MockLobby::new("Practice")).await;
+
+        bancho.bancho_says("#mp_1", "Cookiezi joined in slot 1.");
+        bancho.bancho_says("#mp_1", "WhiteCat joined in slot 2 for team blue.");
+        bancho.bancho_says("#mp_1", "Cookiezi moved to slot 5");
+        client.say("#mp_1", "!mp host WhiteCat");
+        client.say("#mp_1", "!mp mods HD HR");
+        client.say("#mp_1", "!mp map 129891");
+
+        client
+            .wait_until("map changed", |c| {
+                c.lobby("#mp_1")
+                    .and_then(|lobby| lobby.current_map)
+                    .map(|map| map.beatmap_id == 129891)
+                    .unwrap_or(false)
+            })
+            .await;
+
+        let lobby = client.lobby("#mp_1").unwrap();
+        assert!(lobby.slots[0].player.is_none());
+        assert_eq!(lobby.slots[4].player.as_ref().unwrap().username, "Cookiezi");
+        let white_cat = lobby.slots[1].player.as_ref().unwrap();
+        assert_eq!(white_cat.team.as_deref(), Some("blue"));
+        assert!(white_cat.is_host);
+        assert_eq!(lobby.selected_mods, vec!["HD", "HR"]);
+        assert!(!lobby.freemod);
+
+        bancho.bancho_says("#mp_1", "Cookiezi left the game.");
+        client
+            .wait_until("player left", |c| {
+                c.lobby("#mp_1")
+                    .map(|lobby| lobby.slots[4].player.is_none())
+                    .unwrap_or(false)
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn mp_make_joins_the_new_lobby() {
+        let bancho = MockBancho::start().await;
+        let client = TestClient::connect(&bancho).await;
+
+        client.say("BanchoBot", "!mp make Test Lobby");
+        client
+            .wait_until("joining the created lobby", |c| c.lobby("#mp_1").is_some())
+            .await;
+
+        let irc_state = client.state.lock().unwrap();
+        assert!(irc_state.rooms.contains_key("BanchoBot"));
+        assert!(bancho
+            .received()
+            .contains(&"PRIVMSG BanchoBot :!mp make Test Lobby".to_string()));
+    }
+
+    #[tokio::test]
+    async fn create_lobby_configures_the_new_match() {
+        let bancho = MockBancho::start().await;
+        let client = TestClient::connect(&bancho).await;
+
+        let room_id = crate::commands::create_lobby(
+            CreateLobbyOptions {
+                name: "OWC: (A) vs (B)".to_string(),
+                password: Some("secret".to_string()),
+                team_mode: Some("TeamVs".to_string()),
+                win_condition: Some("ScoreV2".to_string()),
+                size: Some(8),
+            },
+            client.app.state(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(room_id, "#mp_1");
+
+        client
+            .wait_until("setup commands sent", |_| {
+                bancho
+                    .received()
+                    .contains(&"PRIVMSG #mp_1 :!mp password secret".to_string())
+            })
+            .await;
+        let received = bancho.received();
+        assert!(received.contains(&"PRIVMSG BanchoBot :!mp make OWC: (A) vs (B)".to_string()));
+        assert!(received.contains(&"PRIVMSG #mp_1 :!mp set 2 3 8".to_string()));
+        assert!(client.state.lock().unwrap().pending_lobbies.is_empty());
+
+        client
+            .wait_until("password confirmed", |c| {
+                c.lobby("#mp_1")
+                    .and_then(|lobby| lobby.settings)
+                    .is_some_and(|settings| settings.has_password)
+            })
+            .await;
+        let lobby = client.lobby("#mp_1").unwrap();
+        // The password itself is never kept in the lobby state or the room's messages
+        assert!(!serde_json::to_string(&lobby).unwrap().contains("secret"));
+        let messages = client.state.lock().unwrap().rooms["#mp_1"].messages.clone();
+        assert!(messages
+            .iter()
+            .any(|message| message.message == "!mp password ******"));
+        assert!(messages
+            .iter()
+            .all(|message| !message.message.contains("secret")));
+        let settings = lobby.settings.unwrap();
+        assert_eq!(settings.team_mode, "TeamVs");
+        assert_eq!(settings.win_condition, "ScoreV2");
+    }
+
+    #[tokio::test]
+    async fn lock_and_size_replies_update_settings() {
+        let (_bancho, client) = joined_lobby(MockLobby::new("Practice")).await;
+        client.say("#mp_1", "!mp size 4");
+        client.say("#mp_1", "!mp lock");
+
+        client
+            .wait_until("lobby locked", |c| {
+                c.lobby("#mp_1")
+                    .and_then(|lobby| lobby.settings)
+                    .is_some_and(|settings| settings.locked)
+            })
+            .await;
+        assert_eq!(client.lobby("#mp_1").unwrap().settings.unwrap().size, 4);
+    }
+
+    #[tokio::test]
+    async fn closed_lobby_is_kept_read_only() {
+        let (_bancho, client) = joined_lobby(team_lobby()).await;
+        client.say("#mp_1", "!mp close");
+
+        client
+            .wait_until("lobby closed", |c| !c.events("lobby-closed").is_empty())
+            .await;
+        // Give the PART that follows the close message time to arrive
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let irc_state = client.state.lock().unwrap();
+        let room = irc_state.rooms.get("#mp_1").unwrap();
+        assert!(room.closed);
+        assert_eq!(room.lobby_state.as_ref().unwrap().match_status, "closed");
+    }
+
+    #[tokio::test]
+    async fn dropped_connection_is_reported_as_lost() {
+        let bancho = MockBancho::start().await;
+        let client = TestClient::connect(&bancho).await;
+        client
+            .wait_until("welcome", |c| !c.events("is-authenticated").is_empty())
+            .await;
+
+        bancho.drop_connection();
+        let end = tokio::time::timeout(Duration::from_secs(5), client.handler)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(end, ConnectionEnd::Lost));
+    }
+}
@@ -27,6 +27,20 @@ pub struct OsuApiBeatmapResponse {
     pub beatmapset: OsuApiBeatmapset,
 }
 
+/// Response of `GET /beatmaps?ids[]=...`. Ids that don't exist are simply left out.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OsuApiBeatmapsResponse {
+    pub beatmaps: Vec<OsuApiBeatmapResponse>,
+}
+
+/// One requested id of a batch lookup, with either its data or why it has none.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BeatmapLookup {
+    pub beatmap_id: u64,
+    pub beatmap: Option<BeatmapData>,
+    pub error: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OsuApiBeatmapset {
     pub artist: String,
@@ -34,6 +48,23 @@ pub struct OsuApiBeatmapset {
     pub creator: String,
 }
 
+impl From<OsuApiBeatmapResponse> for BeatmapData {
+    fn from(beatmap: OsuApiBeatmapResponse) -> Self {
+        Self {
+            id: beatmap.id,
+            beatmapset_id: beatmap.beatmapset_id,
+            artist: beatmap.beatmapset.artist,
+            title: beatmap.beatmapset.title,
+            difficulty: beatmap.version,
+            mapper: beatmap.beatmapset.creator,
+            mode: beatmap.mode_int,
+            total_length: beatmap.total_length,
+            bpm: beatmap.bpm,
+            difficulty_rating: beatmap.difficulty_rating,
+        }
+    }
+}
+
 // User data structures
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UserData {
@@ -0,0 +1,172 @@
+use crate::credentials::{self, OAuthToken};
+use crate::database::sqlite_pool;
+use crate::secrets::SecretStore;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use tauri::{Emitter, Manager};
+use tokio::sync::Mutex;
+
+const API_URL: &str = "https://osu.ppy.sh/api/v2";
+/// Exchanges a refresh token for new tokens, the client secret stays on that server.
+const REFRESH_URL: &str = "https://osureffer.vilaz.dev/refresh-token";
+/// Tokens are refreshed this long before they expire, so a request never races the
+/// expiry.
+const REFRESH_MARGIN: TimeDelta = TimeDelta::seconds(60);
+const NOT_CONNECTED: &str = "osu! account is not connected";
+
+#[derive(Deserialize)]
+struct RefreshResponse {
+    access_token: String,
+    refresh_token: String,
+    expires_in: i64,
+}
+
+/// The one osu! API client of the app. It owns the connected account's tokens,
+/// refreshes them before they expire and shares a connection pool between requests.
+pub struct OsuApiClient {
+    http: reqwest::Client,
+    token: Mutex<Option<OAuthToken>>,
+    app_handle: tauri::AppHandle,
+}
+
+impl OsuApiClient {
+    pub fn new(app_handle: tauri::AppHandle) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            token: Mutex::new(None),
+            app_handle,
+        }
+    }
+
+    /// Loads the stored tokens of an IRC account and refreshes them if they expired.
+    /// Returns whether an osu! account is connected. Without network the tokens are
+    /// kept and the next request tries the refresh again.
+    pub async fn connect(&self, username: &str) -> Result<bool, String> {
+        let pool = sqlite_pool(&self.app_handle).await?;
+        let secrets = self.app_handle.state::<SecretStore>();
+        let stored = credentials::load_oauth_token(&pool, &secrets, username).await?;
+
+        let mut token = self.token.lock().await;
+        *token = stored;
+        if token.as_ref().is_some_and(expires_soon) {
+            if let Err(e) = self.refresh(&mut token).await {
+                println!("Cannot refresh osu! token of {}: {}", username, e);
+            }
+        }
+        Ok(token.is_some())
+    }
+
+    /// Uses tokens that were just saved for the connected account.
+    pub async fn set_token(&self, token: OAuthToken) {
+        *self.token.lock().await = Some(token);
+    }
+
+    pub async fn disconnect(&self) {
+        *self.token.lock().await = None;
+    }
+
+    /// Sends a GET request for an API path like `/beatmaps/75`. A 401 refreshes the
+    /// tokens and retries once, any other status is left to the caller.
+    pub async fn get(&self, path: &str) -> Result<reqwest::Response, String> {
+        let access_token = self.access_token().await?;
+        let response = self.send(path, &access_token).await?;
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let access_token = {
+            let mut token = self.token.lock().await;
+            // Another request may have refreshed the tokens in the meantime
+            if token.as_ref().map(|t| &t.access_token) == Some(&access_token) {
+                self.refresh(&mut token).await?;
+            }
+            token.as_ref().ok_or(NOT_CONNECTED)?.access_token.clone()
+        };
+        self.send(path, &access_token).await
+    }
+
+    async fn send(&self, path: &str, access_token: &str) -> Result<reqwest::Response, String> {
+        self.http
+            .get(format!("{}{}", API_URL, path))
+            .bearer_auth(access_token)
+            .header("Content-Type", "application/json")
+            .send()
+            .await
+            .map_err(|e| format!("osu! API request failed: {}", e))
+    }
+
+    async fn access_token(&self) -> Result<String, String> {
+        let mut token = self.token.lock().await;
+        if token.as_ref().is_some_and(expires_soon) {
+            self.refresh(&mut token).await?;
+        }
+        Ok(token.as_ref().ok_or(NOT_CONNECTED)?.access_token.clone())
+    }
+
+    /// Trades the refresh token for new tokens and stores them. If osu! refuses it the
+    /// account has to be connected again, so the stored tokens are dropped.
+    async fn refresh(&self, token: &mut Option<OAuthToken>) -> Result<(), String> {
+        let Some(current) = token.as_ref() else {
+            return Err(NOT_CONNECTED.to_string());
+        };
+        let username = current.username.clone();
+
+        let url = reqwest::Url::parse_with_params(
+            REFRESH_URL,
+            [("refresh_token", &current.refresh_token)],
+        )
+        .map_err(|e| e.to_string())?;
+        let response = self
+            .http
+            .post(url)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to refresh osu! token: {}", e))?;
+
+        if !response.status().is_success() {
+            println!("osu! token refresh failed: {}", response.status());
+            *token = None;
+            self.forget(&username).await;
+            return Err("osu! session expired, connect your account again".to_string());
+        }
+
+        let refreshed: RefreshResponse = response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse refreshed token: {}", e))?;
+
+        let pool = sqlite_pool(&self.app_handle).await?;
+        let secrets = self.app_handle.state::<SecretStore>();
+        let saved = credentials::save_oauth_token(
+            &pool,
+            &secrets,
+            &username,
+            &refreshed.access_token,
+            &refreshed.refresh_token,
+            refreshed.expires_in,
+        )
+        .await?;
+        *token = Some(saved);
+        Ok(())
+    }
+
+    async fn forget(&self, username: &str) {
+        match sqlite_pool(&self.app_handle).await {
+            Ok(pool) => {
+                let secrets = self.app_handle.state::<SecretStore>();
+                if let Err(e) = credentials::delete_oauth_token(&pool, &secrets, username).await {
+                    println!("Failed to delete osu! tokens of {}: {}", username, e);
+                }
+            }
+            Err(e) => println!("Failed to delete osu! tokens of {}: {}", username, e),
+        }
+        let _ = self.app_handle.emit("osu-account-disconnected", ());
+    }
+}
+
+fn expires_soon(token: &OAuthToken) -> bool {
+    match DateTime::parse_from_rfc3339(&token.expires_at) {
+        Ok(expires_at) => expires_at.with_timezone(&Utc) - REFRESH_MARGIN <= Utc::now(),
+        Err(_) => true,
+    }
+}
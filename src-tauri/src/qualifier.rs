@@ -0,0 +1,302 @@
+use crate::banchobot_parser::LobbyEvent;
+use crate::lobby_reducer::FinishedMap;
+use crate::mappool::{mods_argument, BeatmapEntry};
+use crate::types::MapResult;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QualifierMap {
+    pub beatmap_id: u64,
+    pub category: Option<String>,
+    pub mod_combination: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QualifierPhase {
+    /// The current map is up and the ready timer is running.
+    Waiting,
+    Playing,
+    Finished,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QualifierResult {
+    pub map_index: usize,
+    pub beatmap_id: u64,
+    pub results: Vec<MapResult>,
+}
+
+/// Plays every map of a pool in order: sets map and mods, runs a timer, starts when
+/// everyone is ready or the timer runs out and moves on once the map is finished.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QualifierRun {
+    pub mappool_id: u64,
+    /// The game mode passed along with every `!mp map`.
+    pub mode: u8,
+    pub maps: Vec<QualifierMap>,
+    pub current: usize,
+    pub phase: QualifierPhase,
+    /// A paused run finishes the map being played but doesn't put up the next one.
+    pub paused: bool,
+    pub ready_timer: u32,
+    pub start_delay: u32,
+    pub results: Vec<QualifierResult>,
+}
+
+impl QualifierRun {
+    pub fn new(
+        mappool_id: u64,
+        mode: u8,
+        entries: &[BeatmapEntry],
+        ready_timer: u32,
+        start_delay: u32,
+    ) -> Result<Self, String> {
+        if entries.is_empty() {
+            return Err("The mappool has no maps".to_string());
+        }
+        let maps = entries
+            .iter()
+            .map(|entry| QualifierMap {
+                beatmap_id: entry.beatmap_id as u64,
+                category: entry.category.clone(),
+                mod_combination: entry.mod_combination.clone(),
+            })
+            .collect();
+
+        Ok(Self {
+            mappool_id,
+            mode,
+            maps,
+            current: 0,
+            phase: QualifierPhase::Waiting,
+            paused: false,
+            ready_timer,
+            start_delay,
+            results: Vec::new(),
+        })
+    }
+
+    /// Puts the current map up and starts the ready timer.
+    pub fn setup_commands(&self) -> Vec<String> {
+        let Some(map) = self.maps.get(self.current) else {
+            return Vec::new();
+        };
+
+        vec![
+            format!("!mp map {} {}", map.beatmap_id, self.mode),
+            format!(
+                "!mp mods {}",
+                mods_argument(map.mod_combination.as_deref(), false)
+            ),
+            format!("!mp timer {}", self.ready_timer),
+        ]
+    }
+
+    /// Moves the run along after a lobby event and returns the messages to send.
+    pub fn on_event(&mut self, event: &LobbyEvent, finished: Option<&FinishedMap>) -> Vec<String> {
+        match (event, self.phase) {
+            (LobbyEvent::AllPlayersReady, QualifierPhase::Waiting)
+            | (LobbyEvent::CountdownEnded { aborted: false }, QualifierPhase::Waiting)
+                if !self.paused =>
+            {
+                vec![start_command(self.start_delay)]
+            }
+            (LobbyEvent::MatchStarted, QualifierPhase::Waiting) => {
+                self.phase = QualifierPhase::Playing;
+                Vec::new()
+            }
+            (LobbyEvent::MatchAborted, QualifierPhase::Playing) => {
+                // The map is still selected, so only the timer needs to run again.
+                self.phase = QualifierPhase::Waiting;
+                if self.paused {
+                    Vec::new()
+                } else {
+                    vec![format!("!mp timer {}", self.ready_timer)]
+                }
+            }
+            (LobbyEvent::MatchFinished, QualifierPhase::Playing) => {
+                if let Some(finished) = finished {
+                    self.record_results(finished);
+                }
+                self.advance(self.current + 1)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn pause(&mut self) -> Vec<String> {
+        if self.paused {
+            return Vec::new();
+        }
+        self.paused = true;
+
+        if self.phase == QualifierPhase::Waiting {
+            vec!["!mp aborttimer".to_string()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    pub fn resume(&mut self) -> Vec<String> {
+        if !self.paused {
+            return Vec::new();
+        }
+        self.paused = false;
+
+        if self.phase == QualifierPhase::Waiting {
+            self.setup_commands()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Leaves the current map unplayed, aborting it if it's being played.
+    pub fn skip(&mut self) -> Result<Vec<String>, String> {
+        if self.phase == QualifierPhase::Finished {
+            return Err("The qualifier run is already finished".to_string());
+        }
+        self.go_to(self.current + 1)
+    }
+
+    /// Plays a map of the pool again, replacing its earlier results once finished.
+    pub fn replay(&mut self, map_index: usize) -> Result<Vec<String>, String> {
+        if map_index >= self.maps.len() {
+            return Err(format!("The mappool has no map {}", map_index + 1));
+        }
+        self.go_to(map_index)
+    }
+
+    fn go_to(&mut self, index: usize) -> Result<Vec<String>, String> {
+        let mut messages = Vec::new();
+        if self.phase == QualifierPhase::Playing {
+            messages.push("!mp abort".to_string());
+        }
+        messages.extend(self.advance(index));
+        Ok(messages)
+    }
+
+    fn advance(&mut self, index: usize) -> Vec<String> {
+        self.current = index;
+
+        if index >= self.maps.len() {
+            self.phase = QualifierPhase::Finished;
+            return vec!["That was the last map of the pool, thanks for playing!".to_string()];
+        }
+
+        self.phase = QualifierPhase::Waiting;
+        if self.paused {
+            Vec::new()
+        } else {
+            self.setup_commands()
+        }
+    }
+
+    fn record_results(&mut self, finished: &FinishedMap) {
+        let result = QualifierResult {
+            map_index: self.current,
+            beatmap_id: self.maps[self.current].beatmap_id,
+            results: finished.results.clone(),
+        };
+
+        match self
+            .results
+            .iter_mut()
+            .find(|r| r.map_index == self.current)
+        {
+            Some(existing) => *existing = result,
+            None => self.results.push(result),
+        }
+    }
+}
+
+fn start_command(delay: u32) -> String {
+    if delay == 0 {
+        "!mp start".to_string()
+    } else {
+        format!("!mp start {}", delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run() -> QualifierRun {
+        let entry = |beatmap_id, mods: &str| BeatmapEntry {
+            id: beatmap_id,
+            mappool_id: 1,
+            beatmap_id,
+            artist: String::new(),
+            title: String::new(),
+            difficulty: String::new(),
+            mapper: String::new(),
+            mod_combination: Some(mods.to_string()),
+            category: None,
+            created_at: String::new(),
+            position: 0,
+        };
+        QualifierRun::new(1, 0, &[entry(10, "NM"), entry(20, "HDHR")], 90, 5).unwrap()
+    }
+
+    fn finished() -> FinishedMap {
+        FinishedMap {
+            map_index: 0,
+            results: Vec::new(),
+            scoreline: None,
+            did_not_finish: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn plays_the_pool_in_order() {
+        let mut run = run();
+        assert_eq!(
+            run.setup_commands(),
+            vec!["!mp map 10 0", "!mp mods None", "!mp timer 90"]
+        );
+
+        assert_eq!(
+            run.on_event(&LobbyEvent::CountdownEnded { aborted: false }, None),
+            vec!["!mp start 5"]
+        );
+        run.on_event(&LobbyEvent::MatchStarted, None);
+        assert_eq!(
+            run.on_event(&LobbyEvent::MatchFinished, Some(&finished())),
+            vec!["!mp map 20 0", "!mp mods HD HR", "!mp timer 90"]
+        );
+        assert_eq!(run.results.len(), 1);
+
+        run.on_event(&LobbyEvent::MatchStarted, None);
+        run.on_event(&LobbyEvent::MatchFinished, Some(&finished()));
+        assert_eq!(run.phase, QualifierPhase::Finished);
+    }
+
+    #[test]
+    fn pauses_skips_and_replays() {
+        let mut run = run();
+
+        assert_eq!(run.pause(), vec!["!mp aborttimer"]);
+        assert!(run.on_event(&LobbyEvent::AllPlayersReady, None).is_empty());
+        assert_eq!(run.resume().len(), 3);
+
+        run.on_event(&LobbyEvent::MatchStarted, None);
+        assert_eq!(
+            run.skip().unwrap(),
+            vec![
+                "!mp abort",
+                "!mp map 20 0",
+                "!mp mods HD HR",
+                "!mp timer 90"
+            ]
+        );
+        // The abort we sent shouldn't restart the timer of the skipped map.
+        assert!(run.on_event(&LobbyEvent::MatchAborted, None).is_empty());
+
+        assert_eq!(run.replay(0).unwrap()[0], "!mp map 10 0");
+        assert!(run.replay(5).is_err());
+    }
+}
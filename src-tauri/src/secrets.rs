@@ -0,0 +1,291 @@
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Used as the keyring service name so our entries are easy to find.
+#[cfg(not(target_os = "android"))]
+const SERVICE: &str = "dev.vilaz.osureffer";
+const KEY_FILE: &str = "secrets.key";
+const STORE_FILE: &str = "secrets.json";
+
+/// Keeps passwords and tokens out of the SQLite database. Secrets go to the platform
+/// keyring when there is one and to an encrypted file otherwise. Android has no
+/// keyring we can use, so there they always go to the file.
+pub struct SecretStore {
+    fallback: EncryptedFile,
+}
+
+impl SecretStore {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            fallback: EncryptedFile::new(directory),
+        }
+    }
+
+    pub async fn get(&self, name: &str) -> Result<Option<String>, String> {
+        #[cfg(not(target_os = "android"))]
+        {
+            let lookup = with_keyring(name, |entry| match entry.get_password() {
+                Ok(secret) => Ok(Some(secret)),
+                Err(keyring::Error::NoEntry) => Ok(None),
+                Err(e) => Err(e),
+            });
+            match lookup.await {
+                Ok(Some(secret)) => return Ok(Some(secret)),
+                Ok(None) => {}
+                Err(e) => println!("Keyring unavailable, reading {} from file: {}", name, e),
+            }
+        }
+        self.fallback.get(name)
+    }
+
+    pub async fn set(&self, name: &str, secret: &str) -> Result<(), String> {
+        #[cfg(not(target_os = "android"))]
+        {
+            let value = secret.to_string();
+            match with_keyring(name, move |entry| entry.set_password(&value)).await {
+                // Don't leave an older copy behind in the file
+                Ok(()) => return self.fallback.delete(name),
+                Err(e) => println!("Keyring unavailable, storing {} in file: {}", name, e),
+            }
+        }
+        self.fallback.set(name, secret)
+    }
+
+    pub async fn delete(&self, name: &str) -> Result<(), String> {
+        #[cfg(not(target_os = "android"))]
+        {
+            let removal = with_keyring(name, |entry| match entry.delete_credential() {
+                Err(keyring::Error::NoEntry) => Ok(()),
+                result => result,
+            });
+            if let Err(e) = removal.await {
+                println!("Failed to delete {} from the keyring: {}", name, e);
+            }
+        }
+        self.fallback.delete(name)
+    }
+}
+
+/// Secrets encrypted with AES-256-GCM, for when the keyring can't be used. The key is a
+/// separate file next to them, which keeps secrets out of the database and its copies
+/// but not from anyone who can read this directory. The secret's name is authenticated
+/// with it, so values can't be swapped between entries.
+struct EncryptedFile {
+    directory: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl EncryptedFile {
+    fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn get(&self, name: &str) -> Result<Option<String>, String> {
+        let _guard = self.lock.lock().unwrap();
+        let entries = self.read_entries()?;
+        let Some(sealed) = entries.get(name) else {
+            return Ok(None);
+        };
+        let key = self.load_key()?;
+        open(&key, name, sealed).map(Some)
+    }
+
+    fn set(&self, name: &str, secret: &str) -> Result<(), String> {
+        let _guard = self.lock.lock().unwrap();
+        let key = self.load_key()?;
+        let mut entries = self.read_entries()?;
+        entries.insert(name.to_string(), seal(&key, name, secret)?);
+        self.write_entries(&entries)
+    }
+
+    fn delete(&self, name: &str) -> Result<(), String> {
+        let _guard = self.lock.lock().unwrap();
+        let mut entries = self.read_entries()?;
+        if entries.remove(name).is_some() {
+            self.write_entries(&entries)?;
+        }
+        Ok(())
+    }
+
+    fn read_entries(&self) -> Result<BTreeMap<String, String>, String> {
+        match std::fs::read_to_string(self.directory.join(STORE_FILE)) {
+            Ok(json) => {
+                serde_json::from_str(&json).map_err(|e| format!("Secret store is corrupted: {}", e))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(format!("Failed to read secret store: {}", e)),
+        }
+    }
+
+    fn write_entries(&self, entries: &BTreeMap<String, String>) -> Result<(), String> {
+        let json = serde_json::to_string(entries).map_err(|e| e.to_string())?;
+        write_private(&self.directory.join(STORE_FILE), json.as_bytes())
+            .map_err(|e| format!("Failed to write secret store: {}", e))
+    }
+
+    /// Reads the local key, creating one the first time a secret is stored.
+    fn load_key(&self) -> Result<LessSafeKey, String> {
+        let path = self.directory.join(KEY_FILE);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut bytes = vec![0u8; AES_256_GCM.key_len()];
+                SystemRandom::new()
+                    .fill(&mut bytes)
+                    .map_err(|_| "Failed to generate a secret key".to_string())?;
+                write_private(&path, &bytes)
+                    .map_err(|e| format!("Failed to write secret key: {}", e))?;
+                bytes
+            }
+            Err(e) => return Err(format!("Failed to read secret key: {}", e)),
+        };
+
+        UnboundKey::new(&AES_256_GCM, &bytes)
+            .map(LessSafeKey::new)
+            .map_err(|_| "Secret key is invalid".to_string())
+    }
+}
+
+/// Encrypts a secret and returns the nonce followed by the ciphertext, base64 encoded.
+fn seal(key: &LessSafeKey, name: &str, secret: &str) -> Result<String, String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| "Failed to generate a nonce".to_string())?;
+
+    let mut in_out = secret.as_bytes().to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(name.as_bytes()),
+        &mut in_out,
+    )
+    .map_err(|_| format!("Failed to encrypt {}", name))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(in_out);
+    Ok(base64::engine::general_purpose::STANDARD.encode(sealed))
+}
+
+fn open(key: &LessSafeKey, name: &str, sealed: &str) -> Result<String, String> {
+    let undecryptable = || format!("Cannot decrypt {}", name);
+
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(sealed)
+        .map_err(|_| undecryptable())?;
+    if bytes.len() < NONCE_LEN {
+        return Err(undecryptable());
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| undecryptable())?;
+
+    let mut in_out = ciphertext.to_vec();
+    let plaintext = key
+        .open_in_place(nonce, Aad::from(name.as_bytes()), &mut in_out)
+        .map_err(|_| undecryptable())?;
+    String::from_utf8(plaintext.to_vec()).map_err(|_| undecryptable())
+}
+
+/// Writes a file only the current user can read.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
+}
+
+/// Runs a call on our entry in the platform keyring: the Keychain on macOS, the
+/// Credential Manager on Windows and the Secret Service (GNOME Keyring, KWallet) on
+/// Linux. The platform APIs block, and the Secret Service one deadlocks on a runtime
+/// thread, so calls get a blocking thread of their own.
+#[cfg(not(target_os = "android"))]
+async fn with_keyring<T, F>(name: &str, call: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce(&keyring::Entry) -> keyring::Result<T> + Send + 'static,
+{
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || {
+        let entry = keyring::Entry::new(SERVICE, &name)?;
+        call(&entry)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store(test: &str) -> EncryptedFile {
+        let directory =
+            std::env::temp_dir().join(format!("osureffer-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        EncryptedFile::new(directory)
+    }
+
+    #[test]
+    fn encrypted_file_round_trips() {
+        let store = temp_store("round-trip");
+        store.set("irc-password/Cookiezi", "hunter2").unwrap();
+
+        let raw = std::fs::read_to_string(store.directory.join(STORE_FILE)).unwrap();
+        assert!(!raw.contains("hunter2"));
+        assert_eq!(
+            store.get("irc-password/Cookiezi").unwrap().as_deref(),
+            Some("hunter2")
+        );
+
+        store.delete("irc-password/Cookiezi").unwrap();
+        assert_eq!(store.get("irc-password/Cookiezi").unwrap(), None);
+        let _ = std::fs::remove_dir_all(&store.directory);
+    }
+
+    #[test]
+    fn sealed_values_are_bound_to_their_name() {
+        let store = temp_store("bound");
+        let key = store.load_key().unwrap();
+        let sealed = seal(&key, "irc-password/Cookiezi", "hunter2").unwrap();
+
+        assert!(open(&key, "irc-password/WhiteCat", &sealed).is_err());
+        assert_eq!(
+            open(&key, "irc-password/Cookiezi", &sealed).unwrap(),
+            "hunter2"
+        );
+        let _ = std::fs::remove_dir_all(&store.directory);
+    }
+
+    #[tokio::test]
+    async fn secrets_survive_a_new_store() {
+        let directory = temp_store("restart").directory;
+        let name = format!("irc-password/restart-test-{}", std::process::id());
+
+        SecretStore::new(directory.clone())
+            .set(&name, "hunter2")
+            .await
+            .unwrap();
+
+        let store = SecretStore::new(directory.clone());
+        assert_eq!(store.get(&name).await.unwrap().as_deref(), Some("hunter2"));
+        store.delete(&name).await.unwrap();
+        assert_eq!(store.get(&name).await.unwrap(), None);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::types::IrcCommand;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How many messages may be sent in a burst and how fast the allowance refills.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitConfig {
+    pub burst: u32,
+    pub messages_per_second: f64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        // Stays well below the point where Bancho starts dropping messages for regular accounts
+        Self {
+            burst: 4,
+            messages_per_second: 1.0,
+        }
+    }
+}
+
+/// Where a message goes in the send queue.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SendPriority {
+    #[default]
+    Normal,
+    /// Sent before every normal message, for commands that can't wait.
+    Urgent,
+}
+
+impl SendPriority {
+    /// The priority of a message the referee or auto-ref wants in a lobby. Aborting
+    /// a map or a timer has to happen before whatever is still queued.
+    pub fn of(message: &str) -> Self {
+        let mut words = message.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("!mp"), Some("abort" | "aborttimer")) => SendPriority::Urgent,
+            _ => SendPriority::Normal,
+        }
+    }
+}
+
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let capacity = config.burst.max(1) as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec: config.messages_per_second.max(0.01),
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn configure(&mut self, config: &RateLimitConfig) {
+        self.refill();
+        self.capacity = config.burst.max(1) as f64;
+        self.tokens = self.tokens.min(self.capacity);
+        self.refill_per_sec = config.messages_per_second.max(0.01);
+    }
+
+    pub fn try_take(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Time until at least one message may be sent again.
+    pub fn time_until_available(&mut self) -> Duration {
+        self.refill();
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.refill_per_sec)
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+/// Outgoing chat messages waiting for the rate limiter. Urgent messages skip ahead
+/// of everything else.
+pub struct SendQueue {
+    bucket: TokenBucket,
+    urgent: VecDeque<IrcCommand>,
+    normal: VecDeque<IrcCommand>,
+    reported_depth: usize,
+}
+
+impl SendQueue {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            bucket: TokenBucket::new(config),
+            urgent: VecDeque::new(),
+            normal: VecDeque::new(),
+            reported_depth: 0,
+        }
+    }
+
+    pub fn configure(&mut self, config: &RateLimitConfig) {
+        self.bucket.configure(config);
+    }
+
+    pub fn push(&mut self, command: IrcCommand) {
+        let priority = match &command {
+            IrcCommand::SendMessage { priority, .. } => *priority,
+            _ => SendPriority::Normal,
+        };
+        if priority == SendPriority::Urgent {
+            self.urgent.push_back(command);
+        } else {
+            self.normal.push_back(command);
+        }
+    }
+
+    /// Takes the next message if the rate limit allows sending it right now.
+    pub fn pop_ready(&mut self) -> Option<IrcCommand> {
+        if self.is_empty() || !self.bucket.try_take() {
+            return None;
+        }
+        self.urgent.pop_front().or_else(|| self.normal.pop_front())
+    }
+
+    pub fn time_until_ready(&mut self) -> Duration {
+        self.bucket.time_until_available()
+    }
+
+    pub fn len(&self) -> usize {
+        self.urgent.len() + self.normal.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the queue depth if it changed since the last time it was reported.
+    pub fn depth_changed(&mut self) -> Option<usize> {
+        let depth = self.len();
+        if depth == self.reported_depth {
+            return None;
+        }
+        self.reported_depth = depth;
+        Some(depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(burst: u32, messages_per_second: f64) -> RateLimitConfig {
+        RateLimitConfig {
+            burst,
+            messages_per_second,
+        }
+    }
+
+    fn message(text: &str) -> IrcCommand {
+        IrcCommand::SendMessage {
+            room_id: "#mp_1".to_string(),
+            message: text.to_string(),
+            priority: SendPriority::of(text),
+        }
+    }
+
+    fn text(command: Option<IrcCommand>) -> String {
+        match command {
+            Some(IrcCommand::SendMessage { message, .. }) => message,
+            other => panic!("expected a message, got {:?}", other),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_allows_a_burst_then_waits() {
+        let mut bucket = TokenBucket::new(&config(4, 2.0));
+
+        assert!((0..4).all(|_| bucket.try_take()));
+        assert!(!bucket.try_take());
+        assert_eq!(bucket.time_until_available(), Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_refills_up_to_its_burst() {
+        let mut bucket = TokenBucket::new(&config(2, 2.0));
+        bucket.try_take();
+        bucket.try_take();
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lowering_the_burst_drops_saved_up_tokens() {
+        let mut bucket = TokenBucket::new(&config(4, 1.0));
+        bucket.configure(&config(1, 1.0));
+
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn urgent_messages_jump_the_queue() {
+        let mut queue = SendQueue::new(&config(1, 1.0));
+        queue.push(message("!mp map 75"));
+        queue.push(message("!mp mods HD"));
+        queue.push(message("!mp abort"));
+        assert_eq!(queue.depth_changed(), Some(3));
+
+        assert_eq!(text(queue.pop_ready()), "!mp abort");
+        assert!(queue.pop_ready().is_none());
+        assert_eq!(queue.time_until_ready(), Duration::from_secs(1));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(text(queue.pop_ready()), "!mp map 75");
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(text(queue.pop_ready()), "!mp mods HD");
+        assert!(queue.is_empty());
+        assert_eq!(queue.depth_changed(), Some(0));
+    }
+
+    #[test]
+    fn only_aborts_are_urgent() {
+        assert_eq!(SendPriority::of("!mp abort"), SendPriority::Urgent);
+        assert_eq!(SendPriority::of("  !mp aborttimer"), SendPriority::Urgent);
+        assert_eq!(SendPriority::of("!mp start 10"), SendPriority::Normal);
+        assert_eq!(SendPriority::of("!mp abortion"), SendPriority::Normal);
+        assert_eq!(SendPriority::of("please !mp abort"), SendPriority::Normal);
+    }
+}
@@ -1,3 +1,8 @@
+use crate::auto_ref::AutoRefConfig;
+use crate::draft::{MatchDraft, SavedDraft};
+use crate::mappool::SelectedSlot;
+use crate::qualifier::QualifierRun;
+use crate::send_queue::{RateLimitConfig, SendPriority};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
@@ -19,6 +24,9 @@ pub struct Room {
     pub messages: Vec<IrcMessage>,
     pub unread_count: u32,
     pub lobby_state: Option<LobbyState>,
+    pub has_trimmed_messages: bool,
+    /// BanchoBot closed this lobby, it's kept around read-only for review.
+    pub closed: bool,
 }
 
 impl Room {
@@ -43,6 +51,8 @@ impl Room {
             messages: Vec::new(),
             unread_count: 0,
             lobby_state,
+            has_trimmed_messages: false,
+            closed: false,
         }
     }
 
@@ -54,11 +64,20 @@ impl Room {
             messages: Vec::new(),
             unread_count: 0,
             lobby_state: None,
+            has_trimmed_messages: false,
+            closed: false,
         }
     }
 
+    /// Adds a message to the in-memory history. Older messages are dropped once the
+    /// cap is reached, they stay available from the chat log on disk.
     pub fn add_message(&mut self, message: IrcMessage, is_active: bool) {
         self.messages.push(message);
+        if self.messages.len() > MAX_IN_MEMORY_MESSAGES {
+            let overflow = self.messages.len() - MAX_IN_MEMORY_MESSAGES;
+            self.messages.drain(..overflow);
+            self.has_trimmed_messages = true;
+        }
         if !is_active {
             self.unread_count += 1;
         }
@@ -78,7 +97,7 @@ impl Room {
             messages: self.messages[start..].to_vec(),
             unread_count: self.unread_count,
             lobby_state: self.lobby_state.clone(),
-            has_more_messages: start > 0,
+            has_more_messages: start > 0 || self.has_trimmed_messages,
         }
     }
 
@@ -100,6 +119,14 @@ impl Room {
 }
 
 pub const MESSAGE_PAGE_SIZE: usize = 20;
+pub const MAX_IN_MEMORY_MESSAGES: usize = 500;
+
+pub const DEFAULT_IRC_SERVER: &str = "irc.ppy.sh";
+pub const DEFAULT_IRC_PORT: u16 = 6667;
+pub const DEFAULT_IRC_TLS_PORT: u16 = 6697;
+
+/// Multiplayer lobbies have between 1 and 16 slots.
+pub const MAX_LOBBY_SLOTS: u8 = 16;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -128,6 +155,7 @@ pub struct RoomListItem {
     pub display_name: String,
     pub room_type: RoomType,
     pub unread_count: u32,
+    pub closed: bool,
 }
 
 impl From<&Room> for RoomListItem {
@@ -137,6 +165,7 @@ impl From<&Room> for RoomListItem {
             display_name: room.display_name.clone(),
             room_type: room.room_type.clone(),
             unread_count: room.unread_count,
+            closed: room.closed,
         }
     }
 }
@@ -150,7 +179,7 @@ pub struct RoomsListResponse {
 }
 
 // Lobby state structures
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Player {
     pub username: String,
@@ -158,6 +187,12 @@ pub struct Player {
     pub is_ready: bool,
     pub is_playing: bool,
     pub is_host: bool,
+    /// `!mp settings` listed the player as "No Map".
+    #[serde(default)]
+    pub no_map: bool,
+    /// Was still playing when the last map finished, so has no result for it.
+    #[serde(default)]
+    pub did_not_finish: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -166,7 +201,7 @@ pub struct PlayerSlot {
     pub player: Option<Player>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct CurrentMap {
     pub beatmap_id: u64,
@@ -175,6 +210,128 @@ pub struct CurrentMap {
     pub artist: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MapResult {
+    pub map_index: u32,
+    pub username: String,
+    pub score: u64,
+    pub passed: bool,
+    pub team: Option<String>, // "red" or "blue"
+    pub beatmap_id: Option<u64>,
+    pub mods: Vec<String>,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamMapScore {
+    pub map_index: u32,
+    pub beatmap_id: Option<u64>,
+    pub red_score: u64,
+    pub blue_score: u64,
+    pub winner: Option<String>, // "red", "blue" or None for a draw / undecided map
+    pub picked_by: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchScoreline {
+    pub red_wins: u32,
+    pub blue_wins: u32,
+    pub best_of: Option<u32>,
+    pub maps: Vec<TeamMapScore>,
+    pub match_winner: Option<String>,
+}
+
+impl MatchScoreline {
+    /// Adds up team scores for one played map and decides its winner.
+    /// Only score based win conditions can be decided from BanchoBot's result lines,
+    /// accuracy and combo maps are recorded without a winner.
+    pub fn record_map(&mut self, map_index: u32, results: &[MapResult], win_condition: &str) {
+        let team_total = |team: &str| -> u64 {
+            results
+                .iter()
+                .filter(|result| result.team.as_deref() == Some(team))
+                .map(|result| result.score)
+                .sum()
+        };
+        let red_score = team_total("red");
+        let blue_score = team_total("blue");
+
+        let winner = match win_condition {
+            "Score" | "ScoreV2" if red_score > blue_score => Some("red".to_string()),
+            "Score" | "ScoreV2" if blue_score > red_score => Some("blue".to_string()),
+            _ => None,
+        };
+
+        self.maps.retain(|map| map.map_index != map_index);
+        self.maps.push(TeamMapScore {
+            map_index,
+            beatmap_id: results.iter().find_map(|result| result.beatmap_id),
+            red_score,
+            blue_score,
+            winner,
+            picked_by: None,
+        });
+        self.recompute();
+    }
+
+    pub fn set_map_winner(&mut self, map_index: u32, winner: Option<String>) -> bool {
+        let Some(map) = self.maps.iter_mut().find(|map| map.map_index == map_index) else {
+            return false;
+        };
+        map.winner = winner;
+        self.recompute();
+        true
+    }
+
+    pub fn set_best_of(&mut self, best_of: Option<u32>) {
+        self.best_of = best_of;
+        self.recompute();
+    }
+
+    fn recompute(&mut self) {
+        let count_wins = |team: &str| {
+            self.maps
+                .iter()
+                .filter(|map| map.winner.as_deref() == Some(team))
+                .count() as u32
+        };
+        self.red_wins = count_wins("red");
+        self.blue_wins = count_wins("blue");
+
+        self.match_winner = self.best_of.and_then(|best_of| {
+            let needed = best_of / 2 + 1;
+            if self.red_wins >= needed {
+                Some("red".to_string())
+            } else if self.blue_wins >= needed {
+                Some("blue".to_string())
+            } else {
+                None
+            }
+        });
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Roll {
+    pub username: String,
+    pub value: u64,
+}
+
+/// Captain rolls deciding which team gets the first pick. Only the first roll of each
+/// captain counts, a tie clears both so they roll again.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RollState {
+    pub red: Option<Roll>,
+    pub blue: Option<Roll>,
+    pub winner: Option<String>, // "red" or "blue"
+    pub ties: u32,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct LobbySettings {
@@ -182,7 +339,10 @@ pub struct LobbySettings {
     pub team_mode: String,     // "HeadToHead", "TagCoop", "TeamVs", "TagTeamVs"
     pub win_condition: String, // "Score", "Accuracy", "Combo", "ScoreV2"
     pub size: u8,
-    pub password: Option<String>,
+    /// Only whether there is one, the password itself never ends up in exports or
+    /// the match history.
+    pub has_password: bool,
+    pub locked: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -191,7 +351,7 @@ pub struct LobbyState {
     pub settings: Option<LobbySettings>,
     pub current_map: Option<CurrentMap>,
     pub slots: Vec<PlayerSlot>,
-    pub match_status: String, // "idle", "ready", "starting", "active"
+    pub match_status: String, // "idle", "ready", "starting", "active", "closed"
     pub host: Option<String>,
     pub freemod: bool,
     pub selected_mods: Vec<String>,
@@ -200,11 +360,54 @@ pub struct LobbyState {
     pub map_drain_time: Option<u32>,
     pub timer_start_time: Option<u64>,
     pub timer_duration: Option<u32>,
+    pub maps_played: u32,
+    pub map_results: Vec<MapResult>,
+    pub scoreline: MatchScoreline,
+    pub draft: Option<MatchDraft>,
+    pub rolls: RollState,
+    /// Set when the referee lets the app ready up and start maps on its own.
+    pub auto_ref: Option<AutoRefConfig>,
+    pub qualifier: Option<QualifierRun>,
+    pub selected_slot: Option<SelectedSlot>,
+    /// How the lobby differs from `selected_slot`, empty when it matches.
+    pub slot_mismatches: Vec<String>,
+    /// Counts BanchoBot's "Changed beatmap to" replies, so a command can wait for the
+    /// reply to its own `!mp map`.
+    #[serde(skip)]
+    pub map_changes: u32,
+    /// Whether a `!mp size` or `!mp set` reply told us the lobby size. Until then the
+    /// slots are sized from what `!mp settings` lists.
+    #[serde(skip)]
+    pub size_known: bool,
 }
 
 impl LobbyState {
+    /// Why `!mp start` shouldn't be sent right now, if it shouldn't.
+    pub fn start_blocked(&self) -> Option<String> {
+        let slot = self.selected_slot.as_ref()?;
+        let mismatches = slot.mismatches(self);
+        if mismatches.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "The lobby doesn't match {}: {}",
+            slot.slot_code,
+            mismatches.join(", ")
+        ))
+    }
+
+    /// The draft and scoreline to save, if a draft is set up for this lobby.
+    pub fn saved_draft(&self) -> Option<SavedDraft> {
+        Some(SavedDraft {
+            draft: self.draft.clone()?,
+            scoreline: self.scoreline.clone(),
+        })
+    }
+
     pub fn new() -> Self {
-        let slots = (1..=16).map(|id| PlayerSlot { id, player: None }).collect();
+        let slots = (1..=MAX_LOBBY_SLOTS)
+            .map(|id| PlayerSlot { id, player: None })
+            .collect();
 
         Self {
             settings: None,
@@ -219,6 +422,17 @@ impl LobbyState {
             map_drain_time: None,
             timer_start_time: None,
             timer_duration: None,
+            maps_played: 0,
+            map_results: Vec::new(),
+            scoreline: MatchScoreline::default(),
+            draft: None,
+            rolls: RollState::default(),
+            auto_ref: None,
+            qualifier: None,
+            selected_slot: None,
+            slot_mismatches: Vec::new(),
+            map_changes: 0,
+            size_known: false,
         }
     }
 }
@@ -227,20 +441,44 @@ impl LobbyState {
 #[derive(Debug)]
 pub struct IrcClientState {
     pub connected: bool,
+    pub reconnecting: bool,
     pub rooms: HashMap<String, Room>,
     pub active_room_id: Option<String>,
     pub config: Option<ConnectionConfig>,
     pub client: Option<Arc<Mutex<irc::client::Client>>>,
     pub message_sender: Option<tokio::sync::mpsc::UnboundedSender<IrcCommand>>,
     pub current_username: Option<String>,
+    pub rate_limit: RateLimitConfig,
+    pub pending_lobbies: Vec<PendingLobby>,
+}
+
+/// A `!mp make` waiting for BanchoBot to reply with the new match id.
+#[derive(Debug)]
+pub struct PendingLobby {
+    pub name: String,
+    pub created: tokio::sync::oneshot::Sender<u64>,
 }
 
 #[derive(Debug, Clone)]
 pub enum IrcCommand {
-    SendMessage { room_id: String, message: String },
-    JoinChannel { channel: String },
-    LeaveChannel { channel: String },
-    SendPrivateMessage { username: String, message: String },
+    SendMessage {
+        room_id: String,
+        message: String,
+        priority: SendPriority,
+    },
+    JoinChannel {
+        channel: String,
+    },
+    LeaveChannel {
+        channel: String,
+    },
+    SendPrivateMessage {
+        username: String,
+        message: String,
+    },
+    SetRateLimit {
+        config: RateLimitConfig,
+    },
     Disconnect,
 }
 
@@ -248,16 +486,29 @@ impl Default for IrcClientState {
     fn default() -> Self {
         Self {
             connected: false,
+            reconnecting: false,
             rooms: HashMap::new(),
             active_room_id: None,
             config: None,
             client: None,
             message_sender: None,
             current_username: None,
+            rate_limit: RateLimitConfig::default(),
+            pending_lobbies: Vec::new(),
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateLobbyOptions {
+    pub name: String,
+    pub password: Option<String>,
+    pub team_mode: Option<String>, // "HeadToHead", "TagCoop", "TeamVs", "TagTeamVs"
+    pub win_condition: Option<String>, // "Score", "Accuracy", "Combo", "ScoreV2"
+    pub size: Option<u8>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct IrcMessage {
@@ -269,9 +520,40 @@ pub struct IrcMessage {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct ConnectionConfig {
     pub username: String,
     pub password: String,
+    /// IRC server to connect to, defaults to Bancho.
+    #[serde(default)]
+    pub server: Option<String>,
+    /// Defaults to 6697 with TLS and 6667 without.
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub use_tls: Option<bool>,
+}
+
+impl ConnectionConfig {
+    pub fn server(&self) -> &str {
+        self.server
+            .as_deref()
+            .map(str::trim)
+            .filter(|server| !server.is_empty())
+            .unwrap_or(DEFAULT_IRC_SERVER)
+    }
+
+    pub fn use_tls(&self) -> bool {
+        self.use_tls.unwrap_or(false)
+    }
+
+    pub fn port(&self) -> u16 {
+        match self.port {
+            Some(port) => port,
+            None if self.use_tls() => DEFAULT_IRC_TLS_PORT,
+            None => DEFAULT_IRC_PORT,
+        }
+    }
 }
 
 pub type IrcState = Arc<Mutex<IrcClientState>>;
@@ -1,7 +1,6 @@
 import { computed, watch, type Ref } from 'vue'
 import { useCountdown } from '@vueuse/core'
 import { invoke } from '@tauri-apps/api/core'
-import { dbService } from '@/services/database'
 import { globalState } from '@/stores/global'
 import type { LobbyState, BeatmapData } from '@/types'
 
@@ -15,12 +14,8 @@ export function useMatchCountdown(lobbyState: Readonly<Ref<LobbyState>>, roomId:
       if (oldBeatmapId === undefined && lobbyState.value.mapDrainTime != null) return
 
       try {
-        const accessToken = await dbService.getAccessToken(globalState.user ?? '')
-        if (!accessToken) return
-
         const data = await invoke<BeatmapData>('fetch_beatmap_data', {
           beatmapId: String(beatmapId),
-          accessToken,
         })
         await invoke('set_map_drain_time', {
           roomId: roomId.value,
@@ -1,7 +1,6 @@
 import { ref, onUnmounted } from 'vue'
 import { invoke } from '@tauri-apps/api/core'
 import { avatarCache } from '@/main'
-import { dbService } from '@/services/database'
 import { globalState } from '@/stores/global'
 
 const inflightRequests = new Map<string, Promise<string>>()
@@ -14,15 +13,10 @@ function loadAvatar(username: string): Promise<string> {
   if (inflight) return inflight
 
   const promise = (async () => {
-    const accessToken = await dbService.getAccessToken(globalState.user ?? '')
-    if (!accessToken) throw new Error('No access token')
-
-    const cachedAfterToken = avatarCache.get(username)
-    if (cachedAfterToken !== undefined) return cachedAfterToken
+    if (!globalState.isConnectedOsu) throw new Error('osu! account is not connected')
 
     const userData = await invoke<{ avatar_url?: string }>('fetch_user_data', {
       username,
-      accessToken,
     })
 
     const url = userData.avatar_url
@@ -1,5 +1,5 @@
 import Database from '@tauri-apps/plugin-sql'
-import { fetch } from '@tauri-apps/plugin-http'
+import { invoke } from '@tauri-apps/api/core'
 import { UserCredentials, Mappool, BeatmapEntry } from '@/types'
 
 class DatabaseService {
@@ -18,93 +18,41 @@ class DatabaseService {
   }
 
   async saveCredentials(username: string, password: string): Promise<void> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    const now = new Date().toISOString()
-
-    await this.db.execute(
-      `INSERT INTO user_credentials (username, password, created_at, updated_at)
-       VALUES (?, ?, ?, ?)
-       ON CONFLICT(username) DO UPDATE SET
-         password = excluded.password,
-         updated_at = excluded.updated_at`,
-      [username, password, now, now],
-    )
+    await invoke('save_credentials', { username, password })
   }
 
   async getCredentials(): Promise<UserCredentials | null> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    const result = await this.db.select<UserCredentials[]>(
-      `SELECT id, username, password, created_at, updated_at
-       FROM user_credentials
-       LIMIT 1`,
-    )
-
-    return result.length > 0 ? result[0] : null
+    return await invoke<UserCredentials | null>('get_credentials')
   }
 
   async getOsuConnectedStatus(username: string): Promise<boolean> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    const [result] = await this.db.select<{ id: number, expires_at: string, refresh_token: string }[]>(
-      `SELECT id, expires_at, refresh_token FROM oauth_tokens WHERE irc_username = ? LIMIT 1`,
-      [username],
-    )
-
-    if (!result) return false
-
-    const isExpired = new Date(result.expires_at) <= new Date()
-    if (!isExpired) return true
-
-    return (await this.refreshToken(username, result.refresh_token)) !== null
+    try {
+      return await invoke<boolean>('connect_osu_account', { username })
+    }
+    catch (error) {
+      console.error('Failed to load osu! account:', error)
+      return false
+    }
   }
 
   async deleteCredentials(): Promise<void> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    await this.db.execute('DELETE FROM user_credentials')
+    await invoke('delete_credentials')
   }
 
   async createMappool(name: string, description?: string): Promise<number> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    const now = new Date().toISOString()
-
-    const result = await this.db.execute(
-      `INSERT INTO mappools (name, description, created_at, updated_at)
-       VALUES (?, ?, ?, ?)`,
-      [name, description || null, now, now],
-    )
-
-    return result.lastInsertId || 0
+    return await invoke<number>('create_mappool', { name, description: description || null })
   }
 
   async getMappools(): Promise<Mappool[]> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    return await this.db.select<Mappool[]>(
-      `SELECT id, name, description, created_at, updated_at
-       FROM mappools
-       ORDER BY updated_at DESC`,
-    )
+    return await invoke<Mappool[]>('get_mappools')
   }
 
   async updateMappool(id: number, name: string, description?: string): Promise<void> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    const now = new Date().toISOString()
-
-    await this.db.execute(
-      `UPDATE mappools SET name = ?, description = ?, updated_at = ? WHERE id = ?`,
-      [name, description || null, now, id],
-    )
+    await invoke('update_mappool', { id, name, description: description || null })
   }
 
   async deleteMappool(id: number): Promise<void> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    await this.db.execute('DELETE FROM mappools WHERE id = ?', [id])
+    await invoke('delete_mappool', { id })
   }
 
   async addBeatmapToPool(
@@ -117,119 +65,44 @@ class DatabaseService {
     modCombination?: string,
     category?: string,
   ): Promise<void> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    const now = new Date().toISOString()
-
-    await this.db.execute(
-      `INSERT INTO beatmap_entries
-       (mappool_id, beatmap_id, artist, title, difficulty, mapper, mod_combination, category, created_at, position)
-       VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?,
-         (SELECT COALESCE(MAX(position), -1) + 1 FROM beatmap_entries WHERE mappool_id = ?))`,
-      [mappoolId, beatmapId, artist, title, difficulty, mapper, modCombination || null, category || null, now, mappoolId],
-    )
+    await invoke('add_beatmap_to_pool', {
+      mappoolId,
+      entry: { beatmapId, artist, title, difficulty, mapper, modCombination: modCombination || null, category: category || null },
+    })
   }
 
   async importMappool(
     name: string,
     entries: { beatmapId: number, artist: string, title: string, difficulty: string, mapper: string, mods?: string, category?: string }[],
   ): Promise<number> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    const mappoolId = await this.createMappool(name)
-
-    for (const entry of entries) {
-      await this.addBeatmapToPool(
-        mappoolId,
-        entry.beatmapId,
-        entry.artist,
-        entry.title,
-        entry.difficulty,
-        entry.mapper,
-        entry.mods || undefined,
-        entry.category || undefined,
-      )
-    }
-
-    return mappoolId
+    return await invoke<number>('import_mappool', {
+      name,
+      entries: entries.map(entry => ({
+        beatmapId: entry.beatmapId,
+        artist: entry.artist,
+        title: entry.title,
+        difficulty: entry.difficulty,
+        mapper: entry.mapper,
+        modCombination: entry.mods || null,
+        category: entry.category || null,
+      })),
+    })
   }
 
   async getMappoolBeatmaps(mappoolId: number): Promise<BeatmapEntry[]> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    return await this.db.select<BeatmapEntry[]>(
-      `SELECT id, mappool_id, beatmap_id, artist, title, difficulty, mapper, mod_combination, category, created_at, position
-       FROM beatmap_entries
-       WHERE mappool_id = ?
-       ORDER BY position ASC, created_at ASC, id ASC`,
-      [mappoolId],
-    )
+    return await invoke<BeatmapEntry[]>('get_mappool_beatmaps', { mappoolId })
   }
 
   async updateBeatmapInPool(id: number, category?: string, modCombination?: string): Promise<void> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    await this.db.execute(
-      `UPDATE beatmap_entries SET category = ?, mod_combination = ? WHERE id = ?`,
-      [category || null, modCombination || null, id],
-    )
+    await invoke('update_beatmap_in_pool', { id, category: category || null, modCombination: modCombination || null })
   }
 
   async reorderBeatmaps(orderedIds: number[]): Promise<void> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    for (let i = 0; i < orderedIds.length; i++) {
-      await this.db.execute(
-        'UPDATE beatmap_entries SET position = ? WHERE id = ?',
-        [i, orderedIds[i]],
-      )
-    }
+    await invoke('reorder_beatmaps', { orderedIds })
   }
 
   async deleteBeatmapFromPool(id: number): Promise<void> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    await this.db.execute('DELETE FROM beatmap_entries WHERE id = ?', [id])
-  }
-
-  async getAccessToken(username: string): Promise<string | null> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    const [result] = await this.db.select<{ id: number, expires_at: string, refresh_token: string, access_token: string }[]>(
-      `SELECT id, access_token, expires_at, refresh_token FROM oauth_tokens WHERE irc_username = ? LIMIT 1`,
-      [username],
-    )
-
-    if (!result) return null
-
-    const isExpired = new Date(result.expires_at) <= new Date()
-    if (!isExpired) return result.access_token
-
-    return this.refreshToken(username, result.refresh_token)
-  }
-
-  private async refreshToken(username: string, oldRefreshToken: string): Promise<string | null> {
-    const res = await fetch(
-      `https://osureffer.vilaz.dev/refresh-token?refresh_token=${oldRefreshToken}`,
-      { method: 'POST' },
-    )
-
-    if (!res.ok) {
-      await this.db!.execute('DELETE FROM oauth_tokens WHERE irc_username = ?', [username])
-      console.error('Failed to refresh token:', res.status, res.statusText)
-      return null
-    }
-
-    const resJson = await res.json()
-    try {
-      await this.saveOAuthToken(username, resJson.access_token, resJson.refresh_token, resJson.expires_in)
-      return resJson.access_token
-    }
-    catch (error) {
-      await this.db!.execute('DELETE FROM oauth_tokens WHERE irc_username = ?', [username])
-      console.error('Failed to save refreshed token:', error)
-      return null
-    }
+    await invoke('delete_beatmap_from_pool', { id })
   }
 
   async saveOAuthToken(
@@ -238,28 +111,19 @@ class DatabaseService {
     refreshToken: string,
     expiresIn: number,
   ): Promise<void> {
-    if (!this.db) throw new Error('Database not initialized')
-
-    const now = new Date()
-    const expiresAt = new Date(now.getTime() + expiresIn * 1000).toISOString()
-
-    await this.db.execute(
-      `INSERT INTO oauth_tokens (irc_username, access_token, refresh_token, expires_in, expires_at, created_at, updated_at)
-       VALUES (?, ?, ?, ?, ?, ?, ?)
-       ON CONFLICT(irc_username) DO UPDATE SET
-         access_token = excluded.access_token,
-         refresh_token = excluded.refresh_token,
-         expires_in = excluded.expires_in,
-         expires_at = excluded.expires_at,
-         updated_at = excluded.updated_at`,
-      [username, accessToken, refreshToken, expiresIn, expiresAt, now.toISOString(), now.toISOString()],
-    )
+    await invoke('save_oauth_token', { username, accessToken, refreshToken, expiresIn })
   }
 
   async deleteOauthToken(username: string): Promise<void> {
-    if (!this.db) throw new Error('Database not initialized')
+    await invoke('delete_oauth_token', { username })
+  }
+
+  async getBeatmapCacheTtl(): Promise<number> {
+    return await invoke<number>('get_beatmap_cache_ttl')
+  }
 
-    await this.db.execute('DELETE FROM oauth_tokens WHERE irc_username = ?', [username])
+  async setBeatmapCacheTtl(hours: number): Promise<void> {
+    await invoke('set_beatmap_cache_ttl', { hours })
   }
 }
 
@@ -1,11 +1,9 @@
 export type OAuthToken = {
-  id: number
+  username: string
   access_token: string
   refresh_token: string
   expires_in: number
   expires_at: string
-  created_at: string
-  updated_at: string
 }
 
 export type OauthTokenCallback = Pick<OAuthToken, 'access_token' | 'refresh_token' | 'expires_in'>
@@ -17,3 +15,11 @@ export type UserCredentials = {
   created_at: string
   updated_at: string
 }
+
+export type ConnectionConfig = {
+  username: string
+  password: string
+  server?: string
+  port?: number
+  useTls?: boolean
+}
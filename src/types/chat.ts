@@ -13,6 +13,7 @@ export type RoomBase = {
   messages: IrcMessage[]
   unreadCount: number
   hasMoreMessages: boolean
+  closed: boolean
 }
 
 export type MessagesPage = {
@@ -75,3 +76,26 @@ export type RoomsListUpdatedEvent = {
   rooms: RoomListItem[]
   activeRoomId: string | null
 }
+
+export type ReconnectingEvent = {
+  attempt: number
+  maxAttempts: number
+  delaySecs: number
+}
+
+export type SendQueueUpdatedEvent = {
+  depth: number
+  delayed: boolean
+}
+
+export type RateLimitConfig = {
+  burst: number
+  messagesPerSecond: number
+}
+
+export type ChatSearchResult = {
+  message: IrcMessage
+  snippet: string
+}
+
+export type ExportFormat = 'text' | 'json' | 'markdown'
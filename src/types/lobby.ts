@@ -4,6 +4,8 @@ export type Player = {
   isReady: boolean
   isPlaying: boolean
   isHost: boolean
+  noMap: boolean
+  didNotFinish: boolean
 }
 
 export type PlayerMoveEvent = {
@@ -28,19 +30,154 @@ export type CurrentMap = {
   artist: string
 }
 
+export type MapResult = {
+  mapIndex: number
+  username: string
+  score: number
+  passed: boolean
+  team: 'red' | 'blue' | null
+  beatmapId: number | null
+  mods: string[]
+  timestamp: number
+}
+
+export type MapFinishedEvent = {
+  roomId: string
+  mapIndex: number
+  beatmapId: number | null
+  results: MapResult[]
+  didNotFinish: string[]
+}
+
+export type TeamMapScore = {
+  mapIndex: number
+  beatmapId: number | null
+  redScore: number
+  blueScore: number
+  winner: 'red' | 'blue' | null
+  pickedBy: 'red' | 'blue' | null
+}
+
+export type MatchScoreline = {
+  redWins: number
+  blueWins: number
+  bestOf: number | null
+  maps: TeamMapScore[]
+  matchWinner: 'red' | 'blue' | null
+}
+
+export type TeamScoreUpdatedEvent = {
+  roomId: string
+  scoreline: MatchScoreline
+}
+
+export type DraftActionKind = 'protect' | 'ban' | 'pick'
+
+export type DraftConfig = {
+  redCaptain: string | null
+  blueCaptain: string | null
+  firstPick: 'red' | 'blue'
+  firstBan: 'red' | 'blue'
+  protectsPerTeam: number
+  bansPerTeam: number
+  tiebreakerBeatmapId: number | null
+}
+
+export type DraftAction = {
+  kind: DraftActionKind
+  team: 'red' | 'blue'
+  beatmapId: number
+  timestamp: number
+}
+
+export type MatchDraft = {
+  config: DraftConfig
+  actions: DraftAction[]
+  currentPick: DraftAction | null
+}
+
+export type Roll = {
+  username: string
+  value: number
+}
+
+export type RollState = {
+  red: Roll | null
+  blue: Roll | null
+  winner: 'red' | 'blue' | null
+  ties: number
+}
+
+export type RollDecidedEvent = {
+  roomId: string
+  winner: 'red' | 'blue'
+  red: Roll
+  blue: Roll
+}
+
+export type RollTiedEvent = {
+  roomId: string
+  value: number
+}
+
+export type AutoRefConfig = {
+  startDelay: number
+  readyTimer: number | null
+  abortWindow: number
+  abortMessage: string
+}
+
+export type QualifierMap = {
+  beatmapId: number
+  category: string | null
+  modCombination: string | null
+}
+
+export type QualifierResult = {
+  mapIndex: number
+  beatmapId: number
+  results: MapResult[]
+}
+
+export type QualifierRun = {
+  mappoolId: number
+  mode: number
+  maps: QualifierMap[]
+  current: number
+  phase: 'waiting' | 'playing' | 'finished'
+  paused: boolean
+  readyTimer: number
+  startDelay: number
+  results: QualifierResult[]
+}
+
+export type SelectedSlot = {
+  slotCode: string
+  beatmapId: number
+  mods: string[]
+  freemod: boolean
+}
+
+export type SlotMismatchEvent = {
+  roomId: string
+  slotCode: string
+  mismatches: string[]
+}
+
 export type LobbySettings = {
   roomName: string
   teamMode: 'HeadToHead' | 'TagCoop' | 'TeamVs' | 'TagTeamVs'
   winCondition: 'Score' | 'Accuracy' | 'Combo' | 'ScoreV2'
   size: number
-  password?: string
+  hasPassword: boolean
+  locked: boolean
 }
 
 export type LobbyState = {
   settings: LobbySettings | null
   currentMap: CurrentMap | null
   slots: PlayerSlot[]
-  matchStatus: 'idle' | 'ready' | 'starting' | 'active'
+  matchStatus: 'idle' | 'ready' | 'starting' | 'active' | 'closed'
   host: string | null
   freemod: boolean
   selectedMods: string[]
@@ -49,10 +186,35 @@ export type LobbyState = {
   mapDrainTime: number | null
   timerStartTime: number | null
   timerDuration: number | null
+  mapsPlayed: number
+  mapResults: MapResult[]
+  scoreline: MatchScoreline
+  draft: MatchDraft | null
+  rolls: RollState
+  autoRef: AutoRefConfig | null
+  qualifier: QualifierRun | null
+  selectedSlot: SelectedSlot | null
+  slotMismatches: string[]
 }
 
-export type CreateLobbySettings = {
+export type CreateLobbyOptions = {
   name: string
-  teamMode: '0' | '1' | '2' | '3'
-  scoreMode: '0' | '1' | '2' | '3'
+  password?: string
+  teamMode?: LobbySettings['teamMode']
+  winCondition?: LobbySettings['winCondition']
+  size?: number
+}
+
+export type MatchHistorySummary = {
+  id: number
+  roomId: string
+  roomName: string | null
+  mapsPlayed: number
+  redWins: number
+  blueWins: number
+  closedAt: string
+}
+
+export type MatchHistoryEntry = MatchHistorySummary & {
+  lobbyState: LobbyState
 }
@@ -33,6 +33,12 @@ export type BeatmapData = {
   difficulty_rating: number
 }
 
+export type BeatmapLookup = {
+  beatmap_id: number
+  beatmap: BeatmapData | null
+  error: string | null
+}
+
 export type NewMappoolForm = Omit<Mappool, 'id' | 'created_at' | 'updated_at'>
 
 export type ExtractedEntry = {